@@ -1,43 +1,83 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
-use reqwest::Error;
+use reqwest::{Error, RequestBuilder, Response, StatusCode};
 
 use crate::{
     api::mcathome::platforms::PlatformListResponse,
     manager::platform::Platform,
 };
-use crate::api::mcathome::assignments::{RetrieveTaskOfProjectsRequest, RetrieveTaskOfProjectsResponse};
+use crate::api::mcathome::assignments::{AssignmentInfo, RetrieveTaskOfProjectsRequest, RetrieveTaskOfProjectsResponse};
 use crate::api::mcathome::projects::{
     GetProjectsForPlatformsRequest, GetProjectsForPlatformsResponse,
 };
 use crate::api::mcathome::results::{SubmitResultRequest, SubmitResultResponse};
-use crate::data::assignment::{Assignment, AssignmentResult};
+use crate::data::assignment::AssignmentResult;
 use crate::data::project::{Project, ProjectPlatform};
+use crate::health::Reachability;
+use crate::metrics::METRICS;
+use crate::util::secret::Secret;
 
 #[derive(Debug, Clone)]
 pub struct MCAtHomeAPI {
     client: reqwest::Client,
-    api_key: String,
+    api_key: Secret,
+    base_url: String,
+    reachability: Reachability,
 }
 
 impl MCAtHomeAPI {
-    const BASE_URL: &'static str = "https://api.microboinc.com";
+    pub const BASE_URL: &'static str = "https://api.microboinc.com";
 
-    pub fn new(api_key: &str) -> MCAtHomeAPI {
+    pub fn new(api_key: Secret, base_url: &str) -> MCAtHomeAPI {
         MCAtHomeAPI {
             client: reqwest::Client::new(),
-            api_key: api_key.to_string(),
+            api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            reachability: Reachability::new(),
+        }
+    }
+
+    /// Returns whether the server answered the last requests, shared by every clone of the client.
+    pub fn get_reachability(&self) -> &Reachability {
+        &self.reachability
+    }
+
+    /// Sends an authenticated request, failing on error statuses, and notes whether the server
+    /// answered.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let result = request
+            .header("Authorization", self.api_key.expose())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match &result {
+            Ok(_) => self.reachability.reached(),
+            Err(err) => self.reachability.failed(err),
+        }
+        result
+    }
+
+    /// Checks that the API is reachable and accepts the API key.
+    pub async fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.list_platforms().await {
+            Ok(_) => Ok(()),
+            Err(err) => match err.status() {
+                Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => Err(format!(
+                    "the API key was rejected by {} ({}), check that it's correct and hasn't been revoked",
+                    self.base_url,
+                    err.status().unwrap()
+                )
+                .into()),
+                _ => Err(format!("unable to reach {}: {}", self.base_url, err).into()),
+            },
         }
     }
 
     pub async fn list_platforms(&self) -> Result<Vec<Platform>, Error> {
-        let url = format!("{}/platforms/list", MCAtHomeAPI::BASE_URL);
+        let url = format!("{}/platforms/list", self.base_url);
         let resp = self
-            .client
-            .get(&url)
-            .header("Authorization", self.api_key.to_string())
-            .send()
+            .send(self.client.get(&url))
             .await?
             .json::<PlatformListResponse>()
             .await?;
@@ -58,21 +98,17 @@ impl MCAtHomeAPI {
     pub async fn get_projects_for_platforms(
         &self,
         platforms: &HashMap<i64, Platform>,
-    ) -> Result<Vec<Project>, Error> {
+    ) -> Result<Vec<Project>, Box<dyn std::error::Error>> {
         let platform_ids = platforms
-            .iter()
-            .map(|(_, p)| p.id)
+            .values()
+            .map(|p| p.id)
             .collect::<Vec<i64>>();
 
-        let url = format!("{}/projects/compatible", MCAtHomeAPI::BASE_URL);
+        let url = format!("{}/projects/compatible", self.base_url);
         let body = GetProjectsForPlatformsRequest { platform_ids };
 
         let response = self
-            .client
-            .post(&url)
-            .header("Authorization", self.api_key.to_string())
-            .json(&body)
-            .send()
+            .send(self.client.post(&url).json(&body))
             .await?
             .json::<GetProjectsForPlatformsResponse>()
             .await?;
@@ -82,13 +118,18 @@ impl MCAtHomeAPI {
             let project = match projects.entry(binary.project.id) {
                 Entry::Occupied(entry) => entry.into_mut(),
                 Entry::Vacant(entry) => {
-                    entry.insert(Project::new(binary.project.id, &binary.project.name))
+                    let mut project = Project::new(binary.project.id, &binary.project.name);
+                    project.cores = binary.project.core_count.unwrap_or(1).max(1);
+                    entry.insert(project)
                 }
             };
 
-            let platform = platforms
-                .get(&binary.platform_id)
-                .expect("Platform not found");
+            let platform = platforms.get(&binary.platform_id).ok_or_else(|| {
+                format!(
+                    "the server returned a binary of project {} for unknown platform {}",
+                    binary.project.id, binary.platform_id
+                )
+            })?;
 
             project.add_platform(ProjectPlatform::new(
                 platform.clone(),
@@ -97,48 +138,31 @@ impl MCAtHomeAPI {
             ));
         }
 
-        let mut projects: Vec<Project> = projects.into_iter().map(|(_, v)| v).collect();
-        projects.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut projects: Vec<Project> = projects.into_values().collect();
+        projects.sort_by_key(|p| p.id);
         Ok(projects)
     }
 
-    pub async fn get_assignments(&self, projects: &Vec<Project>) -> Result<Vec<Assignment>, Error> {
-        let project_ids = projects
-            .iter()
-            .map(|p| p.id)
-            .collect::<Vec<i64>>();
-
-        let url = format!("{}/feeder/ofprojects", MCAtHomeAPI::BASE_URL);
-        let body = RetrieveTaskOfProjectsRequest { task_count: 1, project_ids };
+    /// Asks the feeder for a task of one of the given projects.
+    ///
+    /// The returned assignments reference their project by id only, since the feeder may hand
+    /// out tasks of projects the client does not know about yet.
+    pub async fn get_assignments(&self, project_ids: &[i64]) -> Result<Vec<AssignmentInfo>, Error> {
+        let url = format!("{}/feeder/ofprojects", self.base_url);
+        let body = RetrieveTaskOfProjectsRequest { task_count: 1, project_ids: project_ids.to_vec() };
+        let _timer = METRICS.feeder_latency_seconds.start_timer();
 
         let resp = self
-            .client
-            .post(&url)
-            .header("Authorization", self.api_key.to_string())
-            .json(&body)
-            .send()
+            .send(self.client.post(&url).json(&body))
             .await?
             .json::<RetrieveTaskOfProjectsResponse>()
             .await?;
 
-        let assignments = resp
-            .assignments
-            .into_iter()
-            .map(|assignment| {
-                let project = projects
-                    .iter()
-                    .find(|p| p.id == assignment.task.project_id)
-                    .expect("Project not found");
-
-                Assignment::new(assignment.id, project.to_owned(), assignment.task.input_data)
-            })
-            .collect::<Vec<Assignment>>();
-
-        Ok(assignments)
+        Ok(resp.assignments)
     }
 
     pub async fn submit_result(&self, result: &AssignmentResult) -> Result<SubmitResultResponse, Error> {
-        let url = format!("{}/results/submit", MCAtHomeAPI::BASE_URL);
+        let url = format!("{}/results/submit", self.base_url);
         let body = SubmitResultRequest {
             execution_time: result.execution_time,
             assignment_id: result.id,
@@ -147,16 +171,10 @@ impl MCAtHomeAPI {
             exit_code: result.status as i64,
         };
 
-        Ok(
-            self
-                .client
-                .post(&url)
-                .header("Authorization", self.api_key.to_string())
-                .json(&body)
-                .send()
-                .await?
-                .json::<SubmitResultResponse>()
-                .await?
-        )
+        self
+            .send(self.client.post(&url).json(&body))
+            .await?
+            .json::<SubmitResultResponse>()
+            .await
     }
 }
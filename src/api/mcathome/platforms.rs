@@ -13,7 +13,6 @@ pub struct PlatformInfo {
 
 #[derive(Debug, Deserialize)]
 pub struct BinaryInfo {
-    pub id: i64,
     pub checksum: String,
 
     #[serde(rename = "downloadURL")]
@@ -10,16 +10,12 @@ pub struct GetProjectsForPlatformsRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct GetProjectsForPlatformsResponse {
-    #[serde(rename = "projectsIDs")]
-    pub project_ids: Vec<i32>,
-
     #[serde(rename = "projectsBinaries")]
     pub project_binaries: Vec<ProjectBinary>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ProjectBinary {
-    pub id: i64,
     pub priority: i32,
 
     #[serde(rename = "platformID")]
@@ -33,4 +29,8 @@ pub struct ProjectBinary {
 pub struct ProjectInfo {
     pub id: i64,
     pub name: String,
+
+    /// Number of cores each task of the project uses, if the project declares it.
+    #[serde(default, rename = "coreCount")]
+    pub core_count: Option<usize>,
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use simplelog::{error, info};
+use tokio::sync::mpsc;
+
+use crate::api::mcathome::api::MCAtHomeAPI;
+use crate::config::{Config, ConfigHandle};
+use crate::control::{ControlServers, Controller};
+use crate::dashboard;
+use crate::health::{HealthChecker, HealthServer};
+use crate::history::{History, HISTORY_FILE};
+use crate::hooks::HookRunner;
+use crate::logging::{self, LOG_DIR};
+use crate::manager::batch::{Batch, BatchLimits, BatchSummary};
+use crate::manager::catalog::{Catalog, CatalogRefresher};
+use crate::manager::pause::PauseSwitch;
+use crate::manager::pool::PoolManager;
+use crate::manager::reload::{ConfigLoader, ConfigReloader};
+use crate::manager::scheduler::Scheduler;
+use crate::manager::status::StatusBoard;
+use crate::manager::suspect::SuspectBinaries;
+use crate::manager::worker::WorkerContext;
+use crate::metrics::MetricsServer;
+use crate::report::StatusReporter;
+use crate::stats::EfficiencyRefresher;
+use crate::telemetry;
+use crate::util::data_dir::DataDir;
+
+/// How long to wait for running hooks when exiting.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Creates an API client from the config and checks that the server accepts the API key.
+pub async fn connect(config: &Config) -> Result<MCAtHomeAPI, Box<dyn std::error::Error>> {
+    let api = MCAtHomeAPI::new(config.get_api_key()?, &config.base_url);
+    api.validate().await?;
+    Ok(api)
+}
+
+/// Runs a worker until the batch `limits` are reached, reloading `config` with `load` on SIGHUP
+/// or when one of the files at `paths` changes. With `dashboard`, the terminal shows the
+/// [`dashboard`] instead of the log until it's closed.
+///
+/// Without limits this never returns unless the client fails to start.
+pub async fn run(
+    config: Config,
+    load: ConfigLoader,
+    paths: Vec<PathBuf>,
+    limits: BatchLimits,
+    dashboard: bool,
+) -> Result<BatchSummary, Box<dyn std::error::Error>> {
+    // Keep the data directory locked for as long as the client runs
+    let data_dir = DataDir::open(&config.data_dir)?;
+    if config.log_file.enabled {
+        logging::open_file(&data_dir.path().join(LOG_DIR), &config.log_file)?;
+    }
+    let history = History::open(&data_dir.path().join(HISTORY_FILE))?;
+    let telemetry = telemetry::init(&config.tracing)?;
+    let control = ControlServers::bind(&config, data_dir.path())?;
+    let metrics = match config.get_metrics_address()? {
+        Some(address) => Some(MetricsServer::bind(&address)?),
+        None => None,
+    };
+    let health = match config.get_health_address()? {
+        Some(address) => Some(HealthServer::bind(&address)?),
+        None => None,
+    };
+
+    let pools = config.get_pools();
+    info!("");
+    info!("<bold><blue>DICC Client</>");
+    info!("<bold><blue>Version: 0.1.0</>");
+    info!("<bold><blue>Using {} workers</>", pools.iter().map(|p| p.size).sum::<usize>());
+    info!("<bold><blue>Data directory: {}</>", data_dir.path().display());
+    info!("");
+
+    // Fetch platforms and projects
+    info!("<green><bold>Fetching platforms and projects...</>");
+    let api = connect(&config).await?;
+    let catalog = Catalog::fetch(&api, &config.data_dir, &config.get_priorities()).await?;
+    catalog.print();
+
+    let scheduler = Scheduler::new(config.get_policy());
+    let (sender, config) = ConfigHandle::new(config);
+    let (hook_runner, hooks) = HookRunner::new(&config);
+    let (refresher, catalog) = CatalogRefresher::new(&api, catalog, &config, &hooks);
+
+    info!("<green><bold>Creating threads...</>");
+    let context = WorkerContext {
+        config,
+        api: api.clone(),
+        catalog: catalog.clone(),
+        scheduler: scheduler.clone(),
+        suspects: SuspectBinaries::new(),
+        batch: Batch::new(limits),
+        status: StatusBoard::new(),
+        pause: PauseSwitch::new(),
+        history,
+        hooks,
+    };
+
+    let reporter = StatusReporter::new(&context, data_dir.path())?;
+
+    // Dedicated pools first, then the floating default pool with the remaining workers
+    let mut manager = PoolManager::new(&context);
+    manager.apply(pools);
+
+    let (changes, requests) = mpsc::unbounded_channel();
+    let reloader = ConfigReloader::new(load, paths, sender, manager, &scheduler, &catalog, requests);
+    let efficiency = EfficiencyRefresher::new(&context.history, &scheduler);
+    let status = context.status.clone();
+    let checker = HealthChecker::new(&context);
+    let hooks = context.hooks.clone();
+    let controller = Controller::new(&context, changes);
+
+    // Results are submitted before a task finishes, so nothing is lost by exiting once they're done
+    let batch = context.batch.clone();
+    let result = tokio::select! {
+        _ = async {
+            let metrics = async {
+                match metrics {
+                    Some(metrics) => metrics.run(status).await,
+                    None => futures::future::pending().await,
+                }
+            };
+            let health = async {
+                match health {
+                    Some(health) => health.run(checker).await,
+                    None => futures::future::pending().await,
+                }
+            };
+            let dashboard = async {
+                if dashboard {
+                    if let Err(err) = dashboard::run(controller.clone()).await {
+                        error!("<red>Dashboard failed: {}</>", err);
+                    }
+                }
+            };
+            tokio::join!(
+                refresher.run(),
+                reloader.run(),
+                hook_runner.run(),
+                hooks.watch_server(api.get_reachability()),
+                efficiency.run(),
+                reporter.run(),
+                control.run(controller.clone()),
+                metrics,
+                health,
+                dashboard
+            )
+        } => {
+            Ok(batch.get_summary())
+        }
+        summary = batch.wait() => Ok(summary),
+    };
+
+    // Let the hooks of the last assignments run before exiting
+    hook_runner.finish(HOOK_TIMEOUT).await;
+    telemetry.shutdown().await;
+    result
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use simplelog::info;
+
+use dicc_client::config::Config;
+use dicc_client::util::data_dir::DataDir;
+use dicc_client::util::file::{format_size, get_size};
+
+/// Prints the disk space used by the cached detectors, binaries and inputs.
+pub fn show(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = &config.data_dir;
+    println!("Data directory: {}", dir.display());
+    println!("{:>10}  platform detectors", format_size(get_size(&dir.join("platforms"))));
+
+    let mut total = get_size(&dir.join("platforms"));
+    let mut projects = list_dir(&dir.join("projects"))?;
+    projects.sort_by_key(|name| name.parse::<i64>().unwrap_or(i64::MAX));
+    for project in projects {
+        let project_dir = dir.join("projects").join(&project);
+        let binaries = get_size(&project_dir.join("bin"));
+        let inputs = get_size(&project_dir.join("inputs"));
+        println!(
+            "{:>10}  project {} ({} binaries, {} inputs)",
+            format_size(binaries + inputs),
+            project,
+            format_size(binaries),
+            format_size(inputs)
+        );
+        total += binaries + inputs;
+    }
+
+    println!("{:>10}  total", format_size(total));
+    Ok(())
+}
+
+/// Removes the leftover inputs, or everything that can be downloaded again with `all`.
+///
+/// The data directory is locked first, so this can't run under a running client.
+pub fn clean(config: &Config, all: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = DataDir::open(&config.data_dir)?;
+    let projects = data_dir.path().join("projects");
+
+    let mut freed = 0;
+    if all {
+        for dir in [data_dir.path().join("platforms"), projects] {
+            freed += remove(&dir)?;
+        }
+    } else {
+        for project in list_dir(&projects)? {
+            freed += remove(&projects.join(project).join("inputs"))?;
+        }
+    }
+
+    info!("<green><bold>Freed {}.</>", format_size(freed));
+    Ok(())
+}
+
+fn list_dir(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Removes a directory, returning how many bytes it held.
+fn remove(path: &Path) -> Result<u64, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let size = get_size(path);
+    std::fs::remove_dir_all(path).map_err(|e| format!("unable to remove {}: {}", path.display(), e))?;
+    Ok(size)
+}
@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+use dicc_client::config::Config;
+
+/// Prints the effective configuration and the files it was loaded from, with secrets redacted.
+pub fn show(config: &Config, sources: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    for source in sources {
+        println!("# Loaded from {}", source.display());
+    }
+    print!("{}", config.to_redacted_toml()?);
+    Ok(())
+}
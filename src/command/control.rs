@@ -0,0 +1,176 @@
+use simplelog::info;
+
+use dicc_client::config::Config;
+use dicc_client::control::{ClientStatus, ControlRequest, ControlResponse};
+use dicc_client::manager::status::Activity;
+
+/// Sends a request to the client running with the same data directory.
+pub async fn send(config: &Config, request: &ControlRequest) -> Result<ClientStatus, Box<dyn std::error::Error>> {
+    let response = request_socket(config, request).await?;
+    response
+        .status
+        .ok_or_else(|| "the client responded without its status".into())
+}
+
+#[cfg(not(target_os = "windows"))]
+async fn request_socket(config: &Config, request: &ControlRequest) -> Result<ControlResponse, Box<dyn std::error::Error>> {
+    dicc_client::control::socket::send(&dicc_client::control::get_socket_path(&config.data_dir), request).await
+}
+
+#[cfg(target_os = "windows")]
+async fn request_socket(_: &Config, _: &ControlRequest) -> Result<ControlResponse, Box<dyn std::error::Error>> {
+    Err("the control socket isn't supported on Windows, use control.http".into())
+}
+
+pub async fn pause(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    send(config, &ControlRequest::Pause).await?;
+    info!("<green><bold>Paused, running tasks will finish.</>");
+    Ok(())
+}
+
+pub async fn resume(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    send(config, &ControlRequest::Resume).await?;
+    info!("<green><bold>Resumed.</>");
+    Ok(())
+}
+
+pub async fn drain(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let status = send(config, &ControlRequest::Drain).await?;
+    let running = status
+        .workers
+        .iter()
+        .filter(|w| !matches!(w.activity, Activity::Idle | Activity::Paused | Activity::Dead { .. }))
+        .count();
+    info!("<green><bold>Draining, the client exits once {} busy worker(s) are done.</>", running);
+    Ok(())
+}
+
+pub async fn set_workers(config: &Config, workers: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let status = send(config, &ControlRequest::SetWorkers { workers }).await?;
+    for pool in &status.pools {
+        info!("<green><bold>Pool {}: {} worker(s)</>", pool.name, pool.size);
+    }
+    Ok(())
+}
+
+/// Checks the health of the running client, or whether it's ready with `ready`, returning the exit
+/// code: 0 if it passes, 1 otherwise.
+pub async fn healthcheck(config: &Config, ready: bool, json: bool) -> Result<i32, Box<dyn std::error::Error>> {
+    let health = request_socket(config, &ControlRequest::Health)
+        .await?
+        .health
+        .ok_or("the client responded without its health")?;
+    let passes = health.passes(ready);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&health)?);
+    } else {
+        let state = match (ready, passes) {
+            (false, true) => "Healthy",
+            (false, false) => "Unhealthy",
+            (true, true) => "Ready",
+            (true, false) => "Not ready",
+        };
+        println!("{}", state);
+        for problem in &health.problems {
+            println!("  {}", problem);
+        }
+    }
+    Ok(if passes { 0 } else { 1 })
+}
+
+/// Prints the status of the running client, as JSON with `json`.
+pub async fn status(config: &Config, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let status = send(config, &ControlRequest::Status).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    let state = if status.draining {
+        "draining"
+    } else if status.paused {
+        "paused"
+    } else {
+        "running"
+    };
+    println!(
+        "DICC Client {}, {} for {}",
+        status.version,
+        state,
+        humantime::format_duration(std::time::Duration::from_secs(status.uptime))
+    );
+    println!(
+        "Tasks: {} succeeded, {} failed, {} queued",
+        status.succeeded, status.failed, status.queued
+    );
+
+    let pools = status
+        .pools
+        .iter()
+        .map(|pool| format!("{} ({})", pool.name, pool.size))
+        .collect::<Vec<String>>();
+    println!("Pools: {}", pools.join(", "));
+    if !status.included_projects.is_empty() {
+        println!("Included projects: {}", status.included_projects.join(", "));
+    }
+    if !status.excluded_projects.is_empty() {
+        println!("Excluded projects: {}", status.excluded_projects.join(", "));
+    }
+
+    if !status.projects.is_empty() {
+        println!("Projects:");
+    }
+    for project in &status.projects {
+        println!(
+            "  {:<5} {:<16} {} running, {} succeeded, {} failed, {:.1}/h",
+            project.project_id, project.project, project.running, project.succeeded, project.failed, project.per_hour
+        );
+    }
+
+    println!("Workers:");
+    for worker in &status.workers {
+        println!(
+            "  #{:<3} {:<12} {} for {}",
+            worker.id,
+            worker.pool,
+            describe(&worker.activity),
+            humantime::format_duration(std::time::Duration::from_secs(worker.elapsed))
+        );
+    }
+    Ok(())
+}
+
+fn describe(activity: &Activity) -> String {
+    match activity {
+        Activity::Idle => "idle".to_string(),
+        Activity::Paused => "paused".to_string(),
+        Activity::Dead { error } => format!("dead: {}", error),
+        Activity::Fetching { projects } => format!("fetching a task of {}", projects.join(", ")),
+        Activity::Downloading {
+            assignment_id,
+            project_id,
+            project,
+            platform,
+        } => format!(
+            "downloading the {} binary for assignment {} of project {} ({})",
+            platform, assignment_id, project, project_id
+        ),
+        Activity::Running {
+            assignment_id,
+            project_id,
+            project,
+            platform,
+        } => format!(
+            "running assignment {} of project {} ({}) on {}",
+            assignment_id, project, project_id, platform
+        ),
+        Activity::Submitting {
+            assignment_id,
+            project_id,
+            project,
+        } => format!(
+            "submitting assignment {} of project {} ({})",
+            assignment_id, project, project_id
+        ),
+    }
+}
@@ -0,0 +1,22 @@
+use simplelog::info;
+
+use dicc_client::client::connect;
+use dicc_client::config::Config;
+use dicc_client::manager::platform::PlatformManager;
+use dicc_client::util::data_dir::DataDir;
+
+/// Downloads and runs the platform detectors, reporting which platforms this host supports.
+pub async fn detect(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = DataDir::open_shared(&config.data_dir)?;
+    let api = connect(config).await?;
+
+    let mut manager = PlatformManager::new();
+    for platform in api.list_platforms().await? {
+        manager.add(platform);
+    }
+
+    info!("<green><bold>Detecting platforms...</>");
+    let platforms = manager.detect(data_dir.path()).await?;
+    info!("<green><bold>Found {} platform(s).</>", platforms.len());
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use simplelog::info;
+
+use dicc_client::client::connect;
+use dicc_client::config::Config;
+use dicc_client::data::assignment::Assignment;
+use dicc_client::manager::catalog::Catalog;
+use dicc_client::manager::pool::ResourceLimits;
+use dicc_client::manager::suspect::SuspectBinaries;
+use dicc_client::manager::worker::BinaryError;
+use dicc_client::util::data_dir::DataDir;
+
+/// Runs a project's binary on a local input file and prints its output.
+///
+/// The server is only asked for the platforms and projects, no assignment is requested and no
+/// result is submitted.
+pub async fn exec(
+    config: &Config,
+    project: &str,
+    input: &Path,
+    platforms: &[String],
+    threads: Option<usize>,
+    timeout: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = DataDir::open_shared(&config.data_dir)?;
+    let api = connect(config).await?;
+    let catalog = Catalog::fetch(&api, data_dir.path(), &config.get_priorities()).await?;
+
+    let project = catalog
+        .projects
+        .iter()
+        .find(|p| p.name == project || p.id.to_string() == project)
+        .ok_or_else(|| format!("project {} not found or not compatible with this host", project))?;
+
+    // Only try the requested platforms, by id or name
+    let platform_ids = catalog
+        .platform_ids
+        .iter()
+        .filter(|id| {
+            let platform = &catalog.platforms[id];
+            platforms.is_empty()
+                || platforms
+                    .iter()
+                    .any(|key| *key == platform.name || *key == platform.id.to_string())
+        })
+        .cloned()
+        .collect::<Vec<i64>>();
+
+    let assignment = Assignment::new(0, project.clone(), String::new());
+    let mut worker = assignment.create_worker(data_dir.path());
+    worker.threads = threads.unwrap_or_else(|| config.get_policy().get_cores(project));
+    let limits = ResourceLimits {
+        timeout: timeout.map(Duration::from_secs),
+        ..ResourceLimits::default()
+    };
+
+    info!("<green><bold>Running {} on {}...</>", project.name, input.display());
+    let result = worker
+        .run_input(input, &platform_ids, &catalog.priorities, &SuspectBinaries::new(), &limits)
+        .await;
+    let output = match result {
+        Ok(output) => output,
+        Err(err) => {
+            // Whatever the binary printed is what's needed to debug it
+            if let Some(BinaryError::Exited { stdout, stderr, .. }) = err.downcast_ref::<BinaryError>() {
+                std::io::stdout().write_all(stdout.as_bytes())?;
+                std::io::stderr().write_all(stderr.as_bytes())?;
+            }
+            return Err(err);
+        }
+    };
+    info!(
+        "<green><bold>Finished in {}ms.</>",
+        Duration::from_nanos(output.execution_time as u64).as_millis()
+    );
+
+    std::io::stdout().write_all(output.output.as_bytes())?;
+    std::io::stderr().write_all(output.error.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use simplelog::{error, info};
+
+use dicc_client::client::connect;
+use dicc_client::config::Config;
+use dicc_client::manager::catalog::Catalog;
+use dicc_client::util::data_dir::DataDir;
+
+/// Downloads the binaries of every allowed project for the platforms of this host, so the
+/// client can start working without waiting for downloads.
+pub async fn fetch(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = DataDir::open_shared(&config.data_dir)?;
+    let api = connect(config).await?;
+
+    let catalog = Catalog::fetch(&api, data_dir.path(), &config.get_priorities()).await?;
+    let filter = config.get_policy().filter;
+
+    info!("<green><bold>Fetching binaries...</>");
+    let mut failed = 0;
+    for project in catalog.projects.iter().filter(|p| filter.is_allowed(p)) {
+        for platform in project.get_platforms(&catalog.platform_ids, &catalog.priorities) {
+            match project.download_binary(platform, data_dir.path(), &|_, _| {}).await {
+                Ok(_) => info!("{} - {}: <green>OK</>", project.name, platform.platform.name),
+                Err(err) => {
+                    error!("{} - {}: <red>FAILED</> ({})", project.name, platform.platform.name, err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("failed to fetch {} binaries", failed).into());
+    }
+    Ok(())
+}
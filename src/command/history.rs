@@ -0,0 +1,144 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use dicc_client::config::Config;
+use dicc_client::history::{History, HistoryEntry, HistoryFilter, Outcome, HISTORY_FILE};
+
+/// Number of entries the table shows without --limit.
+const DEFAULT_LIMIT: usize = 50;
+
+/// How the history is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for HistoryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HistoryFormat, String> {
+        match s {
+            "table" => Ok(HistoryFormat::Table),
+            "csv" => Ok(HistoryFormat::Csv),
+            "json" => Ok(HistoryFormat::Json),
+            _ => Err(format!("invalid format `{}`, expected table, csv or json", s)),
+        }
+    }
+}
+
+/// Parses a time either as RFC 3339 or as a duration before now, e.g. `24h`.
+pub fn parse_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(time) = humantime::parse_rfc3339_weak(s) {
+        return Ok(time);
+    }
+    let duration = humantime::parse_duration(s)
+        .map_err(|_| format!("invalid time `{}`, expected e.g. 24h or 2022-06-01T00:00:00Z", s))?;
+    SystemTime::now()
+        .checked_sub(duration)
+        .ok_or_else(|| format!("invalid time `{}`", s))
+}
+
+/// Prints the assignments recorded in the history.
+///
+/// The database is read without locking the data directory, so this works next to a running
+/// client.
+pub fn history(
+    config: &Config,
+    mut filter: HistoryFilter,
+    format: HistoryFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config.data_dir.join(HISTORY_FILE);
+    if !path.exists() {
+        return Err(format!("no history in {}, the client hasn't run yet", config.data_dir.display()).into());
+    }
+    if format == HistoryFormat::Table && filter.limit.is_none() {
+        filter.limit = Some(DEFAULT_LIMIT);
+    }
+
+    let entries = History::open(&path)?.query(&filter)?;
+    match format {
+        HistoryFormat::Table => print_table(&entries),
+        HistoryFormat::Csv => print_csv(&entries),
+        HistoryFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+    }
+    Ok(())
+}
+
+fn print_table(entries: &[HistoryEntry]) {
+    println!(
+        "{:<20} {:>10}  {:<16} {:<16} {:>9} {:>9} {:>5} {:>9}  Outcome",
+        "Finished", "Assignment", "Project", "Platform", "Wall", "CPU", "Exit", "Output"
+    );
+    for entry in entries {
+        println!(
+            "{:<20} {:>10}  {:<16} {:<16} {:>9} {:>9} {:>5} {:>9}  {}",
+            &entry.finished_at[..19.min(entry.finished_at.len())].replace('T', " "),
+            entry.assignment_id,
+            entry.project,
+            entry.platform.as_deref().unwrap_or("-"),
+            format_seconds(entry.wall_time),
+            format_seconds(entry.cpu_time),
+            entry.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string()),
+            entry.output_size.map(|size| size.to_string()).unwrap_or_else(|| "-".to_string()),
+            entry.outcome.get_name()
+        );
+    }
+
+    let succeeded = entries.iter().filter(|e| e.outcome == Outcome::Succeeded).count();
+    let wall = entries.iter().filter_map(|e| e.wall_time).sum::<f64>();
+    let cpu = entries.iter().filter_map(|e| e.cpu_time).sum::<f64>();
+    println!(
+        "{} assignment(s), {} succeeded, {} failed, {:.1}s wall time, {:.1}s CPU time",
+        entries.len(),
+        succeeded,
+        entries.len() - succeeded,
+        wall,
+        cpu
+    );
+}
+
+fn print_csv(entries: &[HistoryEntry]) {
+    println!(
+        "assignment_id,project_id,project,platform,binary_checksum,started_at,finished_at,wall_time,cpu_time,\
+         exit_code,output_size,error_size,outcome,submission_id,error"
+    );
+    for entry in entries {
+        let fields = [
+            entry.assignment_id.to_string(),
+            entry.project_id.to_string(),
+            entry.project.clone(),
+            entry.platform.clone().unwrap_or_default(),
+            entry.binary_checksum.clone().unwrap_or_default(),
+            entry.started_at.clone(),
+            entry.finished_at.clone(),
+            entry.wall_time.map(|t| format!("{:.3}", t)).unwrap_or_default(),
+            entry.cpu_time.map(|t| format!("{:.3}", t)).unwrap_or_default(),
+            entry.exit_code.map(|code| code.to_string()).unwrap_or_default(),
+            entry.output_size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.error_size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.outcome.get_name().to_string(),
+            entry.submission_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.error.clone().unwrap_or_default(),
+        ];
+        let line = fields.iter().map(|field| quote_csv(field)).collect::<Vec<String>>();
+        println!("{}", line.join(","));
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn quote_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn format_seconds(seconds: Option<f64>) -> String {
+    match seconds {
+        Some(seconds) => format!("{:.1}s", seconds),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,10 @@
+pub mod cache;
+pub mod config;
+pub mod control;
+pub mod detect;
+pub mod exec;
+pub mod fetch;
+pub mod history;
+pub mod projects;
+pub mod run;
+pub mod stats;
@@ -0,0 +1,27 @@
+use simplelog::{info, warn};
+
+use dicc_client::client::connect;
+use dicc_client::config::Config;
+use dicc_client::manager::catalog::Catalog;
+use dicc_client::util::data_dir::DataDir;
+
+/// Lists the projects compatible with this host and their binaries, in order of preference.
+pub async fn list(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = DataDir::open_shared(&config.data_dir)?;
+    let api = connect(config).await?;
+
+    let catalog = Catalog::fetch(&api, data_dir.path(), &config.get_priorities()).await?;
+    if catalog.projects.is_empty() {
+        warn!("<yellow>No project has a binary for the platforms of this host</>");
+        return Ok(());
+    }
+
+    info!("");
+    catalog.print();
+
+    let filter = config.get_policy().filter;
+    for project in catalog.projects.iter().filter(|p| !filter.is_allowed(p)) {
+        info!("<yellow>{} - {} is excluded by the config</>", project.id, project.name);
+    }
+    Ok(())
+}
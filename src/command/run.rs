@@ -0,0 +1,27 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use dicc_client::client;
+use dicc_client::config::Config;
+use dicc_client::manager::batch::BatchLimits;
+use dicc_client::manager::reload::ConfigLoader;
+
+/// Runs the client until it's killed or the batch `limits` are reached, showing the dashboard
+/// with `dashboard`.
+///
+/// Returns the exit code summarizing the processed tasks.
+pub async fn run(
+    config: Config,
+    load: ConfigLoader,
+    paths: Vec<PathBuf>,
+    limits: BatchLimits,
+    dashboard: bool,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    if dashboard && !std::io::stdout().is_terminal() {
+        return Err("--dashboard needs a terminal".into());
+    }
+
+    let summary = client::run(config, load, paths, limits, dashboard).await?;
+    summary.print();
+    Ok(summary.get_exit_code())
+}
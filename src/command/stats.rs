@@ -0,0 +1,65 @@
+use dicc_client::config::Config;
+use dicc_client::history::{History, HistoryFilter, HISTORY_FILE};
+use dicc_client::stats::{self, ProjectStats};
+
+/// Prints the throughput and reliability of each project, from the history.
+///
+/// Like the history command, this works next to a running client.
+pub fn show(
+    config: &Config,
+    filter: HistoryFilter,
+    by_platform: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config.data_dir.join(HISTORY_FILE);
+    if !path.exists() {
+        return Err(format!("no history in {}, the client hasn't run yet", config.data_dir.display()).into());
+    }
+
+    let entries = History::open(&path)?.query(&filter)?;
+    let stats = stats::aggregate(&entries, by_platform);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:>6}  {:<16} {:<16} {:>7} {:>8} {:>9} {:>9} {:>9} {:>8} {:>8}",
+        "Id", "Project", "Platform", "Tasks", "Per hour", "Mean", "p95", "Succeeded", "Failed", "Timeout"
+    );
+    for project in &stats {
+        print_row(project, by_platform);
+    }
+    if stats.is_empty() {
+        println!("No assignments recorded.");
+    }
+    Ok(())
+}
+
+fn print_row(stats: &ProjectStats, by_platform: bool) {
+    let platform = match &stats.platform {
+        Some(platform) => platform.as_str(),
+        None if by_platform => "-",
+        None => "all",
+    };
+    println!(
+        "{:>6}  {:<16} {:<16} {:>7} {:>8.1} {:>9} {:>9} {:>9} {:>7.1}% {:>7.1}%",
+        stats.project_id,
+        stats.project,
+        platform,
+        stats.assignments,
+        stats.per_hour,
+        format_seconds(stats.mean_runtime),
+        format_seconds(stats.p95_runtime),
+        stats.succeeded,
+        stats.failure_rate * 100.0,
+        stats.timeout_rate * 100.0
+    );
+}
+
+fn format_seconds(seconds: Option<f64>) -> String {
+    match seconds {
+        Some(seconds) => format!("{:.1}s", seconds),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use toml::Value;
+
+use crate::api::mcathome::api::MCAtHomeAPI;
+use crate::health::HealthSettings;
+use crate::hooks::HookSettings;
+use crate::logging::{LogFileSettings, LogFormat};
+use crate::manager::pool::{PoolConfig, ResourceLimits};
+use crate::manager::scheduler::{ProjectFilter, ProjectPolicy};
+use crate::report::ReportSettings;
+use crate::telemetry::TracingSettings;
+use crate::util::data_dir::DataDir;
+use crate::util::secret::Secret;
+
+/// Prefix of the environment variables overriding top-level options, e.g. `DICC_WORKERS`.
+const ENV_PREFIX: &str = "DICC_";
+
+/// Options that are unset by default, and so can only be set from the environment by name.
+const OPTIONAL_KEYS: [&str; 4] = ["api_key", "api_key_file", "metrics_address", "health_address"];
+
+/// Runtime options of the client.
+///
+/// Options are layered, each layer overriding the previous one: defaults, the system config
+/// file, the user config file, the `--config` file, `DICC_*` environment variables and finally
+/// command line flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Minecraft@Home API key. Prefer `api_key_file` or `DICC_API_KEY` over the command line.
+    pub api_key: Option<Secret>,
+    /// File containing the API key, only readable by its owner. `-` reads it from stdin.
+    pub api_key_file: Option<PathBuf>,
+    /// Base URL of the Minecraft@Home API.
+    pub base_url: String,
+    /// Directory under which all caches, inputs, state and logs are stored.
+    pub data_dir: PathBuf,
+    /// Worker count of the default pool, 0 for half the CPUs not used by other pools.
+    pub workers: usize,
+    /// Seconds between refreshes of the platform and project lists.
+    pub refresh_interval: u64,
+    /// Seconds to wait before asking for tasks again when there are none.
+    pub idle_sleep: u64,
+    /// Seconds to wait before retrying after a worker error.
+    pub error_sleep: u64,
+    /// Most verbose level of messages to log: off, error, warn, info, debug or trace.
+    pub log_level: String,
+    /// Format of the logs: `human`, or `json` for one JSON object per line.
+    pub log_format: LogFormat,
+    /// Reload the config when one of the config files changes, as well as on SIGHUP.
+    pub watch_config: bool,
+    /// Address to serve Prometheus metrics on at `/metrics`, e.g. `0.0.0.0:9100`.
+    pub metrics_address: Option<String>,
+    /// Address to serve the health checks on at `/healthz` and `/readyz`, e.g. `0.0.0.0:9101`.
+    pub health_address: Option<String>,
+    pub platforms: PlatformSettings,
+    pub projects: ProjectSettings,
+    pub control: ControlSettings,
+    pub log_file: LogFileSettings,
+    pub report: ReportSettings,
+    pub tracing: TracingSettings,
+    pub health: HealthSettings,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pools: Vec<PoolSettings>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookSettings>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlatformSettings {
+    /// Priority overrides for each platform's binaries, by platform id or name.
+    pub priority: BTreeMap<String, i32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectSettings {
+    /// Projects to run, by id or name. Every compatible project is run if empty.
+    pub include: Vec<String>,
+    /// Projects to never run, by id or name.
+    pub exclude: Vec<String>,
+    /// Relative share of compute for each project, 1.0 if not set.
+    pub weights: BTreeMap<String, f64>,
+    /// Maximum number of concurrent tasks for each project.
+    pub max_tasks: BTreeMap<String, usize>,
+    /// Number of cores each task of a project uses, overriding what the project declares.
+    pub cores: BTreeMap<String, usize>,
+    /// Give less compute to projects whose assignments often fail or time out on this host.
+    pub prefer_efficient: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlSettings {
+    /// Listen for control requests on `control/control.sock` in the data directory.
+    pub socket: bool,
+    /// Loopback address to listen for control requests over HTTP on, e.g. `127.0.0.1:7878`.
+    pub http: Option<String>,
+    /// Bearer token required by the HTTP control endpoint.
+    pub token: Option<Secret>,
+}
+
+/// A dedicated worker pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolSettings {
+    pub name: String,
+    pub size: usize,
+    /// Projects the pool runs, by id or name. Every allowed project if empty.
+    #[serde(default)]
+    pub projects: Vec<String>,
+    /// Projects the pool never runs, by id or name.
+    #[serde(default)]
+    pub exclude_projects: Vec<String>,
+    /// Platforms whose binaries the pool may run, by id or name. Every platform if empty.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Seconds after which a binary is killed.
+    pub timeout: Option<u64>,
+    /// Maximum memory of a binary, in MiB.
+    pub memory: Option<u64>,
+    /// Niceness of the binaries.
+    pub nice: Option<i32>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            api_key: None,
+            api_key_file: None,
+            base_url: MCAtHomeAPI::BASE_URL.to_string(),
+            data_dir: DataDir::default_path(),
+            workers: 0,
+            refresh_interval: 3600,
+            idle_sleep: 60,
+            error_sleep: 60,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Human,
+            watch_config: true,
+            metrics_address: None,
+            health_address: None,
+            platforms: PlatformSettings::default(),
+            projects: ProjectSettings::default(),
+            control: ControlSettings::default(),
+            log_file: LogFileSettings::default(),
+            report: ReportSettings::default(),
+            tracing: TracingSettings::default(),
+            health: HealthSettings::default(),
+            pools: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl Default for ControlSettings {
+    fn default() -> ControlSettings {
+        ControlSettings {
+            socket: true,
+            http: None,
+            token: None,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the config files to read, in order, and whether each one must exist.
+    pub fn get_paths(path: Option<&Path>) -> Vec<(PathBuf, bool)> {
+        let mut paths = vec![(PathBuf::from("/etc/dicc-client/config.toml"), false)];
+        if let Some(dir) = dirs::config_dir() {
+            paths.push((dir.join("dicc-client").join("config.toml"), false));
+        }
+        if let Some(path) = path {
+            paths.push((path.to_path_buf(), true));
+        }
+        paths
+    }
+
+    /// Loads the defaults overridden by the config files and environment variables.
+    ///
+    /// Returns the config along with the files it was read from.
+    pub fn load(path: Option<&Path>) -> Result<(Config, Vec<PathBuf>), Box<dyn std::error::Error>> {
+        let mut value = Value::try_from(Config::default())?;
+        let mut sources = Vec::new();
+
+        for (path, required) in Config::get_paths(path) {
+            if !path.exists() && !required {
+                continue;
+            }
+
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("unable to read config file {}: {}", path.display(), e))?;
+            let layer = Value::from_str(&content)
+                .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+
+            // Check each file on its own so errors point at the right one
+            Config::deserialize(merge(value.clone(), layer.clone()))
+                .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+
+            value = merge(value, layer);
+            sources.push(path);
+        }
+
+        // Top-level scalar options can be overridden by environment variables
+        let table = value.as_table_mut().unwrap();
+        for (key, current) in table.iter_mut() {
+            let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            let env = match std::env::var(&name) {
+                Ok(env) => env,
+                Err(_) => continue,
+            };
+
+            *current = match current {
+                Value::Integer(_) => Value::Integer(
+                    env.parse::<i64>()
+                        .map_err(|e| format!("invalid {}: {}", name, e))?,
+                ),
+                Value::Boolean(_) => Value::Boolean(
+                    env.parse::<bool>()
+                        .map_err(|e| format!("invalid {}: {}", name, e))?,
+                ),
+                Value::Table(_) | Value::Array(_) => continue,
+                _ => Value::String(env),
+            };
+        }
+        for key in OPTIONAL_KEYS {
+            if let Ok(env) = std::env::var(format!("{}{}", ENV_PREFIX, key.to_uppercase())) {
+                table.insert(key.to_string(), Value::String(env));
+            }
+        }
+
+        let config = Config::deserialize(value)?;
+        Ok((config, sources))
+    }
+
+    /// Checks the options for values the client can't run with.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        reqwest::Url::parse(&self.base_url).map_err(|e| format!("invalid base_url `{}`: {}", self.base_url, e))?;
+
+        for (name, value) in [
+            ("refresh_interval", self.refresh_interval),
+            ("idle_sleep", self.idle_sleep),
+            ("error_sleep", self.error_sleep),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be at least 1 second", name).into());
+            }
+        }
+
+        self.get_log_level()?;
+
+        for (project, weight) in &self.projects.weights {
+            if !(*weight > 0.0 && weight.is_finite()) {
+                return Err(format!("weight of project {} must be positive, got {}", project, weight).into());
+            }
+        }
+        for (project, cores) in &self.projects.cores {
+            if *cores == 0 {
+                return Err(format!("cores of project {} must be at least 1", project).into());
+            }
+        }
+
+        let mut names = vec!["default"];
+        for pool in &self.pools {
+            if pool.name.is_empty() {
+                return Err("pool names must not be empty".into());
+            }
+            if names.contains(&pool.name.as_str()) {
+                return Err(format!("duplicate pool name `{}`", pool.name).into());
+            }
+            if pool.size == 0 {
+                return Err(format!("pool {} must have at least 1 worker", pool.name).into());
+            }
+            names.push(&pool.name);
+        }
+
+        self.report.validate()?;
+        self.tracing.get_traces_url()?;
+        for hook in &self.hooks {
+            hook.validate()?;
+        }
+
+        self.get_metrics_address()?;
+        self.get_health_address()?;
+        if let Some(address) = &self.control.http {
+            self.control.get_http_address()?;
+            if self.control.token.is_none() {
+                return Err(format!("control.http is set to {} but control.token is not", address).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the API key, reading it from the key file if it's not set directly.
+    pub fn get_api_key(&self) -> Result<Secret, Box<dyn std::error::Error>> {
+        let key = match (&self.api_key, &self.api_key_file) {
+            (Some(key), _) => key.expose().trim().to_string(),
+            (None, Some(path)) if path == Path::new("-") => {
+                let mut key = String::new();
+                std::io::stdin()
+                    .read_line(&mut key)
+                    .map_err(|e| format!("unable to read the API key from stdin: {}", e))?;
+                key.trim().to_string()
+            }
+            (None, Some(path)) => read_key_file(path)?,
+            (None, None) => String::new(),
+        };
+
+        if key.is_empty() {
+            return Err(format!(
+                "no API key configured: set {}API_KEY, api_key_file in the config file or --api-key-file",
+                ENV_PREFIX
+            )
+            .into());
+        }
+        Ok(Secret::new(&key))
+    }
+
+    pub fn get_log_level(&self) -> Result<LevelFilter, Box<dyn std::error::Error>> {
+        LevelFilter::from_str(&self.log_level).map_err(|_| {
+            format!(
+                "invalid log_level `{}`, expected off, error, warn, info, debug or trace",
+                self.log_level
+            )
+            .into()
+        })
+    }
+
+    pub fn get_policy(&self) -> ProjectPolicy {
+        ProjectPolicy {
+            filter: ProjectFilter {
+                include: self.projects.include.clone(),
+                exclude: self.projects.exclude.clone(),
+            },
+            weights: self.projects.weights.clone().into_iter().collect(),
+            max_tasks: self.projects.max_tasks.clone().into_iter().collect(),
+            cores: self.projects.cores.clone().into_iter().collect(),
+            prefer_efficient: self.projects.prefer_efficient,
+        }
+    }
+
+    pub fn get_priorities(&self) -> Vec<(String, i32)> {
+        self.platforms.priority.clone().into_iter().collect()
+    }
+
+    /// Returns the dedicated pools, followed by the floating default pool.
+    pub fn get_pools(&self) -> Vec<PoolConfig> {
+        let mut pools = self.pools.iter().map(PoolSettings::to_pool).collect::<Vec<PoolConfig>>();
+
+        let dedicated = pools.iter().map(|p| p.size).sum::<usize>();
+        let workers = match self.workers {
+            0 => (num_cpus::get() / 2).saturating_sub(dedicated),
+            workers => workers,
+        };
+        pools.push(PoolConfig::new("default", workers));
+        pools
+    }
+
+    pub fn get_metrics_address(&self) -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        match &self.metrics_address {
+            Some(address) => Ok(Some(
+                address
+                    .parse::<SocketAddr>()
+                    .map_err(|e| format!("invalid metrics_address `{}`: {}", address, e))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_health_address(&self) -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        match &self.health_address {
+            Some(address) => Ok(Some(
+                address
+                    .parse::<SocketAddr>()
+                    .map_err(|e| format!("invalid health_address `{}`: {}", address, e))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Serializes the config as TOML, with secrets redacted.
+    pub fn to_redacted_toml(&self) -> Result<String, Box<dyn std::error::Error>> {
+        // Going through a value orders each table's values before its tables, which the
+        // serializer can't do for empty arrays in pools and hooks on its own
+        Ok(toml::to_string_pretty(&Value::try_from(self)?)?)
+    }
+}
+
+/// A handle to the current config, updated whenever the config is reloaded.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    receiver: watch::Receiver<Arc<Config>>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> (watch::Sender<Arc<Config>>, ConfigHandle) {
+        let (sender, receiver) = watch::channel(Arc::new(config));
+        (sender, ConfigHandle { receiver })
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.receiver.borrow().clone()
+    }
+}
+
+impl ControlSettings {
+    /// Returns the address of the HTTP control endpoint, which must be a loopback address.
+    pub fn get_http_address(&self) -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        let address = match &self.http {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+
+        let parsed = address
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("invalid control.http address `{}`: {}", address, e))?;
+        if !parsed.ip().is_loopback() {
+            return Err(format!("control.http must be a loopback address, got {}", address).into());
+        }
+        Ok(Some(parsed))
+    }
+}
+
+impl PoolSettings {
+    pub fn to_pool(&self) -> PoolConfig {
+        let mut pool = PoolConfig::new(&self.name, self.size);
+        pool.projects = ProjectFilter {
+            include: self.projects.clone(),
+            exclude: self.exclude_projects.clone(),
+        };
+        pool.platforms = self.platforms.clone();
+        pool.limits = ResourceLimits {
+            timeout: self.timeout.map(Duration::from_secs),
+            memory: self.memory.map(|mib| mib * 1024 * 1024),
+            nice: self.nice,
+        };
+        pool
+    }
+}
+
+impl FromStr for PoolSettings {
+    type Err = String;
+
+    /// Parses a pool from `NAME=SIZE[,KEY=VALUE...]`.
+    ///
+    /// Keys are `project` and `exclude` (project id or name), `platform` (platform id or
+    /// name), `timeout` (seconds), `memory` (MiB) and `nice`. Lists are built by repeating a key.
+    fn from_str(s: &str) -> Result<PoolSettings, String> {
+        let mut parts = s.split(',');
+        let (name, size) = parts
+            .next()
+            .and_then(|part| part.split_once('='))
+            .ok_or_else(|| format!("expected NAME=SIZE[,KEY=VALUE...], got `{}`", s))?;
+        let size = size
+            .parse::<usize>()
+            .map_err(|e| format!("invalid size `{}`: {}", size, e))?;
+
+        let mut pool = PoolSettings {
+            name: name.to_string(),
+            size,
+            projects: Vec::new(),
+            exclude_projects: Vec::new(),
+            platforms: Vec::new(),
+            timeout: None,
+            memory: None,
+            nice: None,
+        };
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", part))?;
+            let invalid = |e: &dyn std::fmt::Display| format!("invalid {} `{}`: {}", key, value, e);
+
+            match key {
+                "project" => pool.projects.push(value.to_string()),
+                "exclude" => pool.exclude_projects.push(value.to_string()),
+                "platform" => pool.platforms.push(value.to_string()),
+                "timeout" => pool.timeout = Some(value.parse::<u64>().map_err(|e| invalid(&e))?),
+                "memory" => pool.memory = Some(value.parse::<u64>().map_err(|e| invalid(&e))?),
+                "nice" => pool.nice = Some(value.parse::<i32>().map_err(|e| invalid(&e))?),
+                _ => return Err(format!("unknown pool option `{}`", key)),
+            }
+        }
+        Ok(pool)
+    }
+}
+
+/// Reads an API key file, refusing files other users can read.
+fn read_key_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| format!("unable to read API key file {}: {}", path.display(), e))?;
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "API key file {} can be read by other users (mode {:o}), run `chmod 600 {}`",
+                path.display(),
+                mode,
+                path.display()
+            )
+            .into());
+        }
+    }
+    #[cfg(target_os = "windows")]
+    let _ = metadata;
+
+    let key = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read API key file {}: {}", path.display(), e))?;
+    Ok(key.trim().to_string())
+}
+
+/// Merges `layer` into `base`, recursing into tables and replacing everything else.
+fn merge(base: Value, layer: Value) -> Value {
+    match (base, layer) {
+        (Value::Table(mut base), Value::Table(layer)) => {
+            for (key, value) in layer {
+                let value = match base.remove(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => value,
+                };
+                base.insert(key, value);
+            }
+            Value::Table(base)
+        }
+        (_, layer) => layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(content: &str) -> Value {
+        Value::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn merge_recurses_into_tables() {
+        let base = layer("workers = 2\n[control]\nsocket = true\nhttp = \"127.0.0.1:7878\"\n");
+        let merged = merge(base, layer("[control]\nsocket = false\n"));
+
+        assert_eq!(merged, layer("workers = 2\n[control]\nsocket = false\nhttp = \"127.0.0.1:7878\"\n"));
+    }
+
+    #[test]
+    fn merge_replaces_arrays_and_scalars() {
+        let base = layer("workers = 2\n[projects]\ninclude = [\"a\", \"b\"]\n");
+        let merged = merge(base, layer("workers = \"x\"\n[projects]\ninclude = [\"c\"]\n"));
+
+        assert_eq!(merged, layer("workers = \"x\"\n[projects]\ninclude = [\"c\"]\n"));
+    }
+
+    #[test]
+    fn layers_override_the_defaults() {
+        let defaults = Value::try_from(Config::default()).unwrap();
+        let user = layer("workers = 4\n[projects.weights]\nseeds = 2.0\n");
+        let custom = layer("idle_sleep = 5\n[projects.weights]\nmulti = 0.5\n");
+        let config = Config::deserialize(merge(merge(defaults, user), custom)).unwrap();
+
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.idle_sleep, 5);
+        assert_eq!(config.error_sleep, Config::default().error_sleep);
+        assert_eq!(config.projects.weights.get("seeds"), Some(&2.0));
+        assert_eq!(config.projects.weights.get("multi"), Some(&0.5));
+        assert!(config.pools.is_empty());
+    }
+
+    #[test]
+    fn layers_reject_unknown_keys() {
+        let defaults = Value::try_from(Config::default()).unwrap();
+        assert!(Config::deserialize(merge(defaults, layer("worker = 4\n"))).is_err());
+    }
+
+    #[test]
+    fn api_key_files_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("dicc-client-key-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let config = Config {
+            api_key_file: Some(path.clone()),
+            ..Config::default()
+        };
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let err = config.get_api_key().unwrap_err().to_string();
+        assert!(err.contains("can be read by other users"), "{}", err);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(config.get_api_key().unwrap().expose(), "hunter2");
+
+        // A key set directly takes precedence over the file
+        let config = Config {
+            api_key: Some(Secret::new("direct")),
+            ..config
+        };
+        assert_eq!(config.get_api_key().unwrap().expose(), "direct");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pool_from_str() {
+        let pool = PoolSettings::from_str("gpu=2,project=seeds,project=13,exclude=old,platform=linux-avx2,timeout=60")
+            .unwrap();
+        assert_eq!(pool.name, "gpu");
+        assert_eq!(pool.size, 2);
+        assert_eq!(pool.projects, vec!["seeds", "13"]);
+        assert_eq!(pool.exclude_projects, vec!["old"]);
+        assert_eq!(pool.platforms, vec!["linux-avx2"]);
+        assert_eq!(pool.timeout, Some(60));
+        assert_eq!(pool.memory, None);
+
+        let pool = PoolSettings::from_str("small=1,memory=512,nice=10").unwrap();
+        assert_eq!(pool.memory, Some(512));
+        assert_eq!(pool.nice, Some(10));
+        assert!(pool.projects.is_empty());
+    }
+
+    #[test]
+    fn pool_from_str_rejects_invalid_options() {
+        assert!(PoolSettings::from_str("gpu").is_err());
+        assert!(PoolSettings::from_str("gpu=two").is_err());
+        assert!(PoolSettings::from_str("gpu=2,project").is_err());
+        assert!(PoolSettings::from_str("gpu=2,timeout=soon").is_err());
+        assert!(PoolSettings::from_str("gpu=2,cores=4").is_err());
+    }
+}
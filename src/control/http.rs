@@ -0,0 +1,108 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use simplelog::{error, info};
+
+use crate::control::{ControlRequest, ControlResponse, Controller};
+use crate::util::secret::Secret;
+
+/// Serves control requests over HTTP on a loopback address.
+///
+/// `GET /status` returns the status, `GET /stats` the statistics of each project and
+/// `POST /control` takes the same JSON requests as the control socket. Every request requires an
+/// `Authorization: Bearer <token>` header.
+pub struct HttpServer {
+    incoming: AddrIncoming,
+    token: Secret,
+}
+
+impl HttpServer {
+    pub fn bind(address: &SocketAddr, token: &Secret) -> Result<HttpServer, Box<dyn std::error::Error>> {
+        let incoming = AddrIncoming::bind(address)
+            .map_err(|e| format!("unable to listen for control requests on {}: {}", address, e))?;
+        Ok(HttpServer {
+            incoming,
+            token: token.clone(),
+        })
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    pub async fn run(self, controller: Controller) {
+        info!("<green><bold>Listening for control requests on http://{}</>", self.get_address());
+        let token = self.token;
+        let service = make_service_fn(move |_| {
+            let controller = controller.clone();
+            let token = token.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let controller = controller.clone();
+                    let token = token.clone();
+                    async move { Ok::<_, Infallible>(handle(request, &controller, &token).await) }
+                }))
+            }
+        });
+
+        if let Err(err) = Server::builder(self.incoming).serve(service).await {
+            error!("<red>Control endpoint failed: {}</>", err);
+        }
+    }
+}
+
+async fn handle(request: Request<Body>, controller: &Controller, token: &Secret) -> Response<Body> {
+    let authorized = request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "))
+        .map(|value| token.matches(value))
+        .unwrap_or(false);
+    if !authorized {
+        return respond(StatusCode::UNAUTHORIZED, &ControlResponse::error("missing or invalid token"));
+    }
+
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/status") => controller.handle(ControlRequest::Status).await,
+        (&Method::GET, "/stats") => {
+            let request = ControlRequest::Stats {
+                since: None,
+                by_platform: false,
+            };
+            controller.handle(request).await
+        }
+        (&Method::POST, "/control") => {
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(err) => return respond(StatusCode::BAD_REQUEST, &ControlResponse::error(&err.to_string())),
+            };
+            match serde_json::from_slice::<ControlRequest>(&body) {
+                Ok(request) => controller.handle(request).await,
+                Err(err) => {
+                    let response = ControlResponse::error(&format!("invalid request: {}", err));
+                    return respond(StatusCode::BAD_REQUEST, &response);
+                }
+            }
+        }
+        _ => return respond(StatusCode::NOT_FOUND, &ControlResponse::error("not found")),
+    };
+
+    let status = if response.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    respond(status, &response)
+}
+
+fn respond(status: StatusCode, response: &ControlResponse) -> Response<Body> {
+    let body = serde_json::to_vec(response).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
@@ -0,0 +1,483 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use simplelog::info;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::{Config, ConfigHandle};
+use crate::health::{HealthChecker, HealthReport};
+use crate::history::{History, HistoryFilter};
+use crate::manager::batch::Batch;
+use crate::manager::catalog::CatalogHandle;
+use crate::manager::pause::PauseSwitch;
+use crate::manager::reload::{ConfigChange, ConfigChangeRequest};
+use crate::manager::scheduler::Scheduler;
+use crate::manager::status::{StatusBoard, TaskOutcome, WorkerStatus};
+use crate::manager::worker::WorkerContext;
+use crate::stats::{self, ProjectStats};
+
+pub mod http;
+#[cfg(not(target_os = "windows"))]
+pub mod socket;
+
+/// Name of the control socket in its directory.
+pub const SOCKET_FILE: &str = "control.sock";
+
+/// Directory of the control socket in the data directory, only accessible to the user running the client.
+pub const SOCKET_DIR: &str = "control";
+
+/// Returns the path of the control socket of the client using `data_dir`.
+pub fn get_socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SOCKET_DIR).join(SOCKET_FILE)
+}
+
+/// A request to the running client, sent as a JSON object such as `{"command": "pause"}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+    /// Stop asking for new tasks, letting running ones finish.
+    Pause,
+    Resume,
+    /// Finish the running tasks, then exit.
+    Drain,
+    /// Resize the default pool, 0 for half the CPUs not used by other pools.
+    SetWorkers { workers: usize },
+    /// Allow a project again, by id or name as used in the config.
+    EnableProject { project: String },
+    DisableProject { project: String },
+    /// Statistics of each project from the history, see [`stats::aggregate`].
+    Stats {
+        /// Only assignments finished in the last this many seconds.
+        #[serde(default)]
+        since: Option<u64>,
+        #[serde(default)]
+        by_platform: bool,
+    },
+    /// Result of the health checks, see [`HealthChecker`].
+    Health,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The client's status after the request was handled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ClientStatus>,
+    /// The statistics asked for with a `stats` request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Vec<ProjectStats>>,
+    /// The result of a `health` request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthReport>,
+}
+
+/// A snapshot of what the client is doing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientStatus {
+    pub version: String,
+    pub paused: bool,
+    /// Whether the client stopped taking new tasks and exits once the running ones are done.
+    pub draining: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Seconds since the client started.
+    pub uptime: u64,
+    /// Assignments handed out to workers busy with another one.
+    pub queued: usize,
+    pub pools: Vec<PoolStatus>,
+    pub workers: Vec<WorkerStatus>,
+    /// Projects with running or finished tasks.
+    pub projects: Vec<ProjectStatus>,
+    /// The last finished assignments, the most recent first.
+    pub recent: Vec<TaskOutcome>,
+    pub included_projects: Vec<String>,
+    pub excluded_projects: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectStatus {
+    pub project_id: i64,
+    pub project: String,
+    /// Tasks reserved by workers, being fetched, run or submitted.
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Compute used by the project's completed tasks, in core-seconds.
+    pub core_seconds: f64,
+    /// Tasks succeeded per hour since the client started.
+    pub per_hour: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolStatus {
+    pub name: String,
+    pub size: usize,
+}
+
+/// The control endpoints enabled in the config.
+pub struct ControlServers {
+    #[cfg(not(target_os = "windows"))]
+    socket: Option<socket::SocketServer>,
+    http: Option<http::HttpServer>,
+}
+
+impl ControlServers {
+    /// Binds the enabled endpoints, so they fail before the client starts working.
+    pub fn bind(config: &Config, data_dir: &Path) -> Result<ControlServers, Box<dyn std::error::Error>> {
+        let http = match (config.control.get_http_address()?, &config.control.token) {
+            (Some(address), Some(token)) => Some(http::HttpServer::bind(&address, token)?),
+            _ => None,
+        };
+
+        #[cfg(not(target_os = "windows"))]
+        let socket = if config.control.socket {
+            Some(socket::SocketServer::bind(&get_socket_path(data_dir))?)
+        } else {
+            None
+        };
+        #[cfg(target_os = "windows")]
+        let _ = data_dir;
+
+        Ok(ControlServers {
+            #[cfg(not(target_os = "windows"))]
+            socket,
+            http,
+        })
+    }
+
+    /// Serves requests until the client exits.
+    pub async fn run(self, controller: Controller) {
+        #[cfg(not(target_os = "windows"))]
+        let socket = async {
+            match &self.socket {
+                Some(socket) => socket.run(controller.clone()).await,
+                None => futures::future::pending().await,
+            }
+        };
+        #[cfg(target_os = "windows")]
+        let socket = futures::future::pending::<()>();
+
+        let http = async {
+            match self.http {
+                Some(http) => http.run(controller.clone()).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::join!(socket, http);
+    }
+}
+
+/// Handles control requests from any endpoint.
+#[derive(Clone)]
+pub struct Controller {
+    config: ConfigHandle,
+    catalog: CatalogHandle,
+    scheduler: Scheduler,
+    batch: Batch,
+    status: StatusBoard,
+    pause: PauseSwitch,
+    history: History,
+    health: HealthChecker,
+    changes: mpsc::UnboundedSender<ConfigChangeRequest>,
+}
+
+impl Controller {
+    pub fn new(context: &WorkerContext, changes: mpsc::UnboundedSender<ConfigChangeRequest>) -> Controller {
+        Controller {
+            config: context.config.clone(),
+            catalog: context.catalog.clone(),
+            scheduler: context.scheduler.clone(),
+            batch: context.batch.clone(),
+            status: context.status.clone(),
+            pause: context.pause.clone(),
+            history: context.history.clone(),
+            health: HealthChecker::new(context),
+            changes,
+        }
+    }
+
+    pub async fn handle(&self, request: ControlRequest) -> ControlResponse {
+        let mut stats = None;
+        let mut health = None;
+        let result = match request {
+            ControlRequest::Status => Ok(()),
+            ControlRequest::Pause => {
+                if self.pause.pause() {
+                    info!("<yellow><bold>Paused, running tasks will finish.</>");
+                }
+                Ok(())
+            }
+            ControlRequest::Resume => {
+                if self.pause.resume() {
+                    info!("<green><bold>Resumed.</>");
+                }
+                Ok(())
+            }
+            ControlRequest::Drain => {
+                self.batch.drain();
+                Ok(())
+            }
+            ControlRequest::SetWorkers { workers } => self.change(ConfigChange::SetWorkers(workers)).await,
+            ControlRequest::EnableProject { project } => self.change(ConfigChange::EnableProject(project)).await,
+            ControlRequest::DisableProject { project } => {
+                self.change(ConfigChange::DisableProject(project)).await
+            }
+            ControlRequest::Stats { since, by_platform } => {
+                let filter = HistoryFilter {
+                    since: since.and_then(|since| SystemTime::now().checked_sub(Duration::from_secs(since))),
+                    ..HistoryFilter::default()
+                };
+                stats::query(&self.history, filter, by_platform)
+                    .await
+                    .map(|result| stats = Some(result))
+            }
+            ControlRequest::Health => {
+                health = Some(self.health.check().await);
+                Ok(())
+            }
+        };
+
+        match result {
+            Ok(()) => ControlResponse {
+                ok: true,
+                error: None,
+                status: Some(self.get_status()),
+                stats,
+                health,
+            },
+            Err(err) => ControlResponse::error(&err),
+        }
+    }
+
+    pub fn get_status(&self) -> ClientStatus {
+        let config = self.config.get();
+        let summary = self.batch.get_summary();
+        let workers = self.status.get_workers();
+        ClientStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            paused: self.pause.is_paused(),
+            draining: self.batch.is_stopped(),
+            succeeded: summary.succeeded,
+            failed: summary.failed,
+            uptime: self.status.get_uptime().as_secs(),
+            queued: workers.iter().map(|w| w.queued).sum(),
+            pools: config
+                .get_pools()
+                .into_iter()
+                .map(|pool| PoolStatus {
+                    name: pool.name,
+                    size: pool.size,
+                })
+                .collect(),
+            workers,
+            projects: self.get_projects(),
+            recent: self.status.get_recent(),
+            included_projects: config.projects.include.clone(),
+            excluded_projects: config.projects.exclude.clone(),
+        }
+    }
+
+    /// Combines the scheduler's usage with the finished tasks of each project.
+    fn get_projects(&self) -> Vec<ProjectStatus> {
+        let catalog = self.catalog.get();
+        let usage = self.scheduler.get_usage();
+        let totals = self.status.get_projects();
+        let hours = self.status.get_uptime().as_secs_f64() / 3600.0;
+
+        let mut ids = usage.keys().chain(totals.keys()).cloned().collect::<Vec<i64>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .map(|id| {
+                let usage = usage.get(&id);
+                let totals = totals.get(&id).cloned().unwrap_or_default();
+                let project = match catalog.get_project(id) {
+                    Some(project) => project.name.clone(),
+                    None if !totals.project.is_empty() => totals.project.clone(),
+                    None => id.to_string(),
+                };
+                ProjectStatus {
+                    project_id: id,
+                    project,
+                    running: usage.map(|u| u.running).unwrap_or(0),
+                    succeeded: totals.succeeded,
+                    failed: totals.failed,
+                    core_seconds: usage.map(|u| u.seconds).unwrap_or(0.0),
+                    per_hour: if hours > 0.0 { totals.succeeded as f64 / hours } else { 0.0 },
+                }
+            })
+            .collect()
+    }
+
+    /// Has the config reloader apply a change, and waits for it.
+    async fn change(&self, change: ConfigChange) -> Result<(), String> {
+        let (reply, result) = oneshot::channel();
+        self.changes
+            .send((change, reply))
+            .map_err(|_| "the client is shutting down".to_string())?;
+        result.await.map_err(|_| "the client is shutting down".to_string())?
+    }
+}
+
+impl ControlResponse {
+    pub fn error(message: &str) -> ControlResponse {
+        ControlResponse {
+            ok: false,
+            error: Some(message.to_string()),
+            status: None,
+            stats: None,
+            health: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::api::mcathome::api::MCAtHomeAPI;
+    use crate::hooks::HookRunner;
+    use crate::manager::batch::BatchLimits;
+    use crate::manager::catalog::{Catalog, CatalogRefresher};
+    use crate::manager::scheduler::ProjectPolicy;
+    use crate::manager::suspect::SuspectBinaries;
+    use crate::util::secret::Secret;
+
+    fn create_context(name: &str) -> WorkerContext {
+        let data_dir = std::env::temp_dir().join(format!("dicc-client-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let history = History::open(&data_dir.join("history.db")).unwrap();
+        let (_, config) = ConfigHandle::new(Config {
+            data_dir,
+            ..Config::default()
+        });
+
+        let api = MCAtHomeAPI::new(Secret::new("key"), "http://127.0.0.1:1");
+        let (_, hooks) = HookRunner::new(&config);
+        let catalog = Catalog {
+            platforms: HashMap::new(),
+            platform_ids: Vec::new(),
+            projects: Vec::new(),
+            priorities: HashMap::new(),
+        };
+        let (_, catalog) = CatalogRefresher::new(&api, catalog, &config, &hooks);
+        WorkerContext {
+            config,
+            api,
+            catalog,
+            scheduler: Scheduler::new(ProjectPolicy::default()),
+            suspects: SuspectBinaries::new(),
+            batch: Batch::new(BatchLimits::default()),
+            status: StatusBoard::new(),
+            pause: PauseSwitch::new(),
+            history,
+            hooks,
+        }
+    }
+
+    fn create_controller(context: &WorkerContext) -> Controller {
+        Controller::new(context, mpsc::unbounded_channel().0)
+    }
+
+    #[test]
+    fn requests_are_parsed() {
+        let parse = |json: &str| serde_json::from_str::<ControlRequest>(json);
+        assert!(matches!(parse(r#"{"command": "pause"}"#).unwrap(), ControlRequest::Pause));
+        assert!(matches!(
+            parse(r#"{"command": "set_workers", "workers": 4}"#).unwrap(),
+            ControlRequest::SetWorkers { workers: 4 }
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "disable_project", "project": "seeds"}"#).unwrap(),
+            ControlRequest::DisableProject { project } if project == "seeds"
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "stats"}"#).unwrap(),
+            ControlRequest::Stats {
+                since: None,
+                by_platform: false
+            }
+        ));
+
+        assert!(parse(r#"{"command": "reboot"}"#).is_err());
+        assert!(parse(r#"{"command": "set_workers"}"#).is_err());
+        assert!(parse(r#"{"workers": 4}"#).is_err());
+
+        let json = serde_json::to_value(ControlRequest::EnableProject {
+            project: "13".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json, serde_json::json!({"command": "enable_project", "project": "13"}));
+    }
+
+    #[test]
+    fn responses_skip_missing_fields() {
+        let json = serde_json::to_value(ControlResponse::error("invalid request")).unwrap();
+        assert_eq!(json, serde_json::json!({"ok": false, "error": "invalid request"}));
+
+        let response = serde_json::from_str::<ControlResponse>(r#"{"ok": true}"#).unwrap();
+        assert!(response.ok);
+        assert!(response.error.is_none() && response.status.is_none() && response.stats.is_none());
+    }
+
+    #[tokio::test]
+    async fn http_requires_the_token() {
+        let context = create_context("control-http");
+        let server = http::HttpServer::bind(&"127.0.0.1:0".parse().unwrap(), &Secret::new("secret")).unwrap();
+        let address = server.get_address();
+        tokio::spawn(server.run(create_controller(&context)));
+
+        let client = reqwest::Client::new();
+        let get_status = |authorization: Option<&str>| {
+            let mut request = client.get(format!("http://{}/status", address));
+            if let Some(authorization) = authorization {
+                request = request.header(reqwest::header::AUTHORIZATION, authorization);
+            }
+            request.send()
+        };
+        for authorization in [None, Some("Bearer wrong"), Some("Bearer secret2"), Some("Basic secret")] {
+            let response = get_status(authorization).await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED, "{:?}", authorization);
+            let response = response.json::<ControlResponse>().await.unwrap();
+            assert!(!response.ok && response.status.is_none());
+        }
+
+        let response = get_status(Some("Bearer secret")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.json::<ControlResponse>().await.unwrap().status.is_some());
+
+        let response = client
+            .post(format!("http://{}/control", address))
+            .bearer_auth("secret")
+            .body(r#"{"command": "pause"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(context.pause.is_paused());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn socket_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let context = create_context("control-socket");
+        let path = get_socket_path(&context.config.get().data_dir);
+        let server = socket::SocketServer::bind(&path).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        let controller = create_controller(&context);
+        tokio::spawn(async move { server.run(controller).await });
+        let response = socket::send(&path, &ControlRequest::Drain).await.unwrap();
+        assert!(response.ok && response.status.unwrap().draining);
+    }
+}
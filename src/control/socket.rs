@@ -0,0 +1,116 @@
+use std::fs::{DirBuilder, Permissions};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use simplelog::{error, info};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::control::{ControlRequest, ControlResponse, Controller};
+
+/// Serves control requests over a Unix socket, one JSON object per line in each direction.
+///
+/// The socket is only accessible to the user running the client.
+pub struct SocketServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl SocketServer {
+    /// Binds the socket, replacing a stale one left by a client that didn't exit cleanly.
+    ///
+    /// Only call this with the data directory locked, so the socket can't belong to a live client.
+    pub fn bind(path: &Path) -> Result<SocketServer, Box<dyn std::error::Error>> {
+        // Bind in a directory only the user can enter, so nobody else can connect before the socket's
+        // own permissions are restricted
+        if let Some(dir) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .and_then(|_| std::fs::set_permissions(dir, Permissions::from_mode(0o700)))
+                .map_err(|e| format!("unable to create control socket directory {}: {}", dir.display(), e))?;
+        }
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("unable to listen on control socket {}: {}", path.display(), e))?;
+        std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        Ok(SocketServer {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub async fn run(&self, controller: Controller) {
+        info!("<green><bold>Listening for control requests on {}</>", self.path.display());
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let controller = controller.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle(stream, controller).await {
+                            error!("<red>Control connection failed: {}</>", err);
+                        }
+                    });
+                }
+                Err(err) => error!("<red>Unable to accept control connection: {}</>", err),
+            }
+        }
+    }
+}
+
+impl Drop for SocketServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends a request to the client listening on the socket at `path` and returns its response.
+pub async fn send(path: &Path, request: &ControlRequest) -> Result<ControlResponse, Box<dyn std::error::Error>> {
+    let stream = UnixStream::connect(path).await.map_err(|e| {
+        format!(
+            "unable to reach a running client on {}: {}, check --data-dir and that control.socket is enabled",
+            path.display(),
+            e
+        )
+    })?;
+
+    let (reader, mut writer) = stream.into_split();
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or("the client closed the connection without responding")?;
+    let response = serde_json::from_str::<ControlResponse>(&line)?;
+    match response.error {
+        Some(error) if !response.ok => Err(error.into()),
+        _ => Ok(response),
+    }
+}
+
+async fn handle(stream: UnixStream, controller: Controller) -> Result<(), Box<dyn std::error::Error>> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => controller.handle(request).await,
+            Err(err) => ControlResponse::error(&format!("invalid request: {}", err)),
+        };
+
+        let mut data = serde_json::to_vec(&response)?;
+        data.push(b'\n');
+        writer.write_all(&data).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! Full-screen terminal dashboard showing what the workers are doing, in place of the log.
+
+use std::io::{self, Stdout};
+use std::time::{Duration, SystemTime};
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures::StreamExt;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table};
+use tui::{Frame, Terminal};
+
+use crate::control::{ClientStatus, ControlRequest, Controller};
+use crate::logging;
+use crate::manager::status::{Activity, WorkerStatus};
+use crate::util::file::format_size;
+
+/// How often the dashboard is redrawn without input.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shows the dashboard until it's closed with `q`, after which the log is written to the
+/// terminal again.
+///
+/// `p` pauses or resumes the client and `d` drains it. Ctrl-C exits the client right away, as
+/// it would without the dashboard.
+pub async fn run(controller: Controller) -> Result<(), Box<dyn std::error::Error>> {
+    let mut screen = Screen::enter()?;
+    let mut events = EventStream::new();
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        let status = controller.get_status();
+        screen.terminal.draw(|frame| draw(frame, &status))?;
+
+        tokio::select! {
+            _ = interval.tick() => {}
+            event = events.next() => {
+                let key = match event {
+                    Some(Ok(Event::Key(key))) => key,
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Ok(()),
+                };
+                match key {
+                    KeyEvent { code: KeyCode::Char('c'), modifiers, .. }
+                        if modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        // Raw mode swallows SIGINT, so exit the way it would have
+                        drop(screen);
+                        std::process::exit(130);
+                    }
+                    KeyEvent { code: KeyCode::Char('q') | KeyCode::Esc, .. } => return Ok(()),
+                    KeyEvent { code: KeyCode::Char('p'), .. } => {
+                        let request = if status.paused { ControlRequest::Resume } else { ControlRequest::Pause };
+                        controller.handle(request).await;
+                    }
+                    KeyEvent { code: KeyCode::Char('d'), .. } => {
+                        controller.handle(ControlRequest::Drain).await;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn enter() -> Result<Screen, Box<dyn std::error::Error>> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        logging::set_capture(true);
+
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        terminal.hide_cursor()?;
+        Ok(Screen { terminal })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+        logging::set_capture(false);
+    }
+}
+
+fn draw<B: Backend>(frame: &mut Frame<B>, status: &ClientStatus) {
+    let projects = status.projects.len() as u16;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(status.workers.len().min(8) as u16 + 3),
+            Constraint::Length(projects.min(8) + 3),
+            Constraint::Length(10),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+
+    frame.render_widget(Paragraph::new(get_header(status)), chunks[0]);
+    draw_workers(frame, chunks[1], &status.workers);
+    draw_projects(frame, chunks[2], status);
+
+    let bottom = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(chunks[3]);
+    draw_recent(frame, bottom[0], status);
+    draw_log(frame, bottom[1]);
+
+    let help = Paragraph::new("q: back to the log  p: pause/resume  d: drain  Ctrl-C: exit")
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(help, chunks[4]);
+}
+
+fn get_header(status: &ClientStatus) -> Spans<'static> {
+    let (state, color) = if status.draining {
+        ("draining", Color::Yellow)
+    } else if status.paused {
+        ("paused", Color::Yellow)
+    } else {
+        ("running", Color::Green)
+    };
+    let workers = status
+        .workers
+        .iter()
+        .filter(|w| !matches!(w.activity, Activity::Idle | Activity::Paused | Activity::Dead { .. }))
+        .count();
+
+    Spans::from(vec![
+        Span::styled(
+            format!("DICC Client {} ", status.version),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(state, Style::default().fg(color).add_modifier(Modifier::BOLD)),
+        Span::raw(format!(
+            "  up {}  {}/{} workers busy  {} queued  {} succeeded  {} failed",
+            format_duration(status.uptime),
+            workers,
+            status.workers.len(),
+            status.queued,
+            status.succeeded,
+            status.failed
+        )),
+    ])
+}
+
+fn draw_workers<B: Backend>(frame: &mut Frame<B>, area: Rect, workers: &[WorkerStatus]) {
+    let rows = workers.iter().map(|worker| {
+        let (project, assignment, detail) = match &worker.activity {
+            Activity::Idle | Activity::Paused => (String::new(), String::new(), String::new()),
+            Activity::Fetching { projects } => (projects.join(", "), String::new(), String::new()),
+            Activity::Downloading {
+                assignment_id,
+                project,
+                platform,
+                ..
+            } => (
+                project.clone(),
+                assignment_id.to_string(),
+                format!("{} {}", platform, format_download(worker)),
+            ),
+            Activity::Running {
+                assignment_id,
+                project,
+                platform,
+                ..
+            } => (project.clone(), assignment_id.to_string(), platform.clone()),
+            Activity::Submitting {
+                assignment_id, project, ..
+            } => (project.clone(), assignment_id.to_string(), String::new()),
+            Activity::Dead { error } => (String::new(), String::new(), error.clone()),
+        };
+        let color = match worker.activity {
+            Activity::Idle | Activity::Paused => Color::DarkGray,
+            Activity::Running { .. } => Color::Green,
+            Activity::Dead { .. } => Color::Red,
+            _ => Color::Cyan,
+        };
+
+        Row::new(vec![
+            Cell::from(format!("#{}", worker.id)),
+            Cell::from(worker.pool.clone()),
+            Cell::from(worker.activity.get_name()).style(Style::default().fg(color)),
+            Cell::from(project),
+            Cell::from(assignment),
+            Cell::from(format_duration(worker.elapsed)),
+            Cell::from(if worker.queued > 0 { worker.queued.to_string() } else { String::new() }),
+            Cell::from(detail),
+        ])
+    });
+
+    let table = Table::new(rows)
+        .header(header(&[
+            "Worker", "Pool", "State", "Project", "Assignment", "Elapsed", "Queued", "Detail",
+        ]))
+        .block(Block::default().borders(Borders::ALL).title(" Workers "))
+        .widths(&[
+            Constraint::Length(7),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(16),
+            Constraint::Length(11),
+            Constraint::Length(10),
+            Constraint::Length(7),
+            Constraint::Min(24),
+        ]);
+    frame.render_widget(table, area);
+}
+
+fn draw_projects<B: Backend>(frame: &mut Frame<B>, area: Rect, status: &ClientStatus) {
+    let rows = status.projects.iter().map(|project| {
+        Row::new(vec![
+            Cell::from(project.project_id.to_string()),
+            Cell::from(project.project.clone()),
+            Cell::from(project.running.to_string()),
+            Cell::from(project.succeeded.to_string()),
+            Cell::from(project.failed.to_string()).style(if project.failed > 0 {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            }),
+            Cell::from(format!("{:.1}", project.per_hour)),
+            Cell::from(format_duration(project.core_seconds as u64)),
+        ])
+    });
+
+    let table = Table::new(rows)
+        .header(header(&[
+            "Id", "Project", "Running", "Succeeded", "Failed", "Per hour", "Core time",
+        ]))
+        .block(Block::default().borders(Borders::ALL).title(" Projects "))
+        .widths(&[
+            Constraint::Length(6),
+            Constraint::Length(16),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Min(10),
+        ]);
+    frame.render_widget(table, area);
+}
+
+fn draw_recent<B: Backend>(frame: &mut Frame<B>, area: Rect, status: &ClientStatus) {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let items = status
+        .recent
+        .iter()
+        .map(|outcome| {
+            let (result, color) = if outcome.success { ("OK    ", Color::Green) } else { ("FAILED", Color::Red) };
+            ListItem::new(Spans::from(vec![
+                Span::styled(result, Style::default().fg(color)),
+                Span::raw(format!(
+                    " {} of {} in {}, {} ago",
+                    outcome.assignment_id,
+                    outcome.project,
+                    format_duration(outcome.elapsed as u64),
+                    format_duration(now.saturating_sub(outcome.finished))
+                )),
+            ]))
+        })
+        .collect::<Vec<ListItem>>();
+
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(" Recent results "));
+    frame.render_widget(list, area);
+}
+
+fn draw_log<B: Backend>(frame: &mut Frame<B>, area: Rect) {
+    let lines = logging::get_captured();
+    let shown = area.height.saturating_sub(2) as usize;
+    let items = lines[lines.len().saturating_sub(shown)..]
+        .iter()
+        .map(|line| ListItem::new(line.clone()))
+        .collect::<Vec<ListItem>>();
+
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(" Log "));
+    frame.render_widget(list, area);
+}
+
+fn header(titles: &[&'static str]) -> Row<'static> {
+    Row::new(titles.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+fn format_download(worker: &WorkerStatus) -> String {
+    match worker.download {
+        Some(progress) => match progress.total {
+            Some(total) if total > 0 => format!(
+                "{} / {} ({}%)",
+                format_size(progress.downloaded),
+                format_size(total),
+                progress.downloaded * 100 / total
+            ),
+            _ => format_size(progress.downloaded),
+        },
+        None => String::new(),
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -1,3 +1,6 @@
+use std::path::Path;
+use std::time::Duration;
+
 use crate::data::project::Project;
 use crate::manager::worker::ProjectWorker;
 
@@ -15,6 +18,12 @@ pub struct AssignmentResult {
     pub error: String,
     pub status: i32,
     pub execution_time: u128,
+    /// Platform of the binary that produced the result, kept for the history.
+    pub platform: Option<String>,
+    /// Checksum of that binary, kept for the history.
+    pub checksum: Option<String>,
+    /// CPU time used by the binary, where the OS reports it.
+    pub cpu_time: Option<Duration>,
 }
 
 impl Assignment {
@@ -26,9 +35,12 @@ impl Assignment {
         }
     }
 
-    pub fn create_worker(&self) -> ProjectWorker {
+    pub fn create_worker(&self, data_dir: &Path) -> ProjectWorker {
         ProjectWorker {
             assignment: self.clone(),
+            data_dir: data_dir.to_path_buf(),
+            threads: 1,
+            status: None,
         }
     }
 }
@@ -41,6 +53,9 @@ impl AssignmentResult {
             error,
             status,
             execution_time,
+            platform: None,
+            checksum: None,
+            cpu_time: None,
         }
     }
 }
\ No newline at end of file
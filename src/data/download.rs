@@ -1,20 +1,31 @@
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use reqwest::Url;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
 use tokio::{
-    fs::File,
+    fs::{self, File},
     io::{self, AsyncReadExt},
 };
 use tokio::process::Command;
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::metrics::METRICS;
+use crate::util::file::{is_safe_file_name, set_executable};
+
+/// Called with the number of bytes downloaded so far and the size of the download, if known.
+pub type Progress<'a> = &'a (dyn Fn(u64, Option<u64>) + Sync);
+
+/// Tells apart the temporary files of concurrent downloads.
+static PART_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Download {
     url: String,
     checksums: Vec<Checksum>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Checksum {
     algorithm: String,
     value: String,
@@ -58,15 +69,41 @@ impl Download {
         false
     }
 
-    pub fn get_filename(&self) -> String {
-        let mut url = self.url.clone();
-        url.split_off(url.rfind('/').unwrap() + 1)
+    /// Returns the first checksum of the download, e.g. `sha256:...`.
+    pub fn get_checksum(&self) -> Option<String> {
+        self.checksums
+            .first()
+            .map(|checksum| format!("{}:{}", checksum.algorithm, checksum.value))
+    }
+
+    /// Returns the file name at the end of the URL, without its query string.
+    ///
+    /// Fails if the URL has no file name or it's not safe to use as a path component.
+    pub fn get_filename(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let url = Url::parse(&self.url)?;
+        let name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default();
+
+        if is_safe_file_name(name) {
+            Ok(name.to_string())
+        } else {
+            Err(Box::new(io::Error::other(format!(
+                "unsafe file name `{}` in download URL {}",
+                name, self.url
+            ))))
+        }
     }
 
     pub fn get_command(&self, path: &Path) -> Command {
         let mut command = Command::new(path);
 
-        if self.get_filename().ends_with(".jar") {
+        let is_jar = self
+            .get_filename()
+            .map(|name| name.ends_with(".jar"))
+            .unwrap_or(false);
+        if is_jar {
             command = Command::new("java");
             command.arg("-jar");
             command.arg(path);
@@ -75,23 +112,28 @@ impl Download {
         command
     }
 
-    pub async fn download(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let resp = reqwest::get(self.url.as_str())
-            .await?
-            .bytes()
-            .await?
-            .to_vec();
-        if self.verify(&resp) {
-            Ok(resp)
+    pub async fn download(&self, progress: Progress<'_>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut response = reqwest::get(self.url.as_str()).await?;
+        let total = response.content_length();
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            data.extend_from_slice(&chunk);
+            METRICS.download_bytes.inc_by(chunk.len() as u64);
+            progress(data.len() as u64, total);
+        }
+        if self.verify(&data) {
+            Ok(data)
         } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                "Checksum verification failed",
-            )))
+            Err(Box::new(io::Error::other("Checksum verification failed")))
         }
     }
 
-    pub async fn download_to_file(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    /// Makes sure `path` holds a verified, executable copy of the download.
+    pub async fn download_to_file(
+        &self,
+        path: &Path,
+        progress: Progress<'_>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         if path.exists() {
             // Verify the file
             let mut file = File::open(path).await?;
@@ -99,21 +141,66 @@ impl Download {
             file.read_to_end(&mut data).await?;
 
             if self.verify(&data) {
+                METRICS.cache_hits.inc();
                 return Ok(());
             }
         }
 
         // Fallback to download
-        if let Ok(data) = self.download().await {
-            let mut file = File::create(&path).await?;
+        METRICS.cache_misses.inc();
+        let data = match self.download(progress).await {
+            Ok(data) => data,
+            Err(err) => return Err(format!("Download failed: {}", err).into()),
+        };
+
+        // Write next to the target and rename it in place, another worker may be running the
+        // cached copy and must never see a partially written file
+        let part = path.with_extension(format!(
+            "part-{}-{}",
+            std::process::id(),
+            PART_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = async {
+            let mut file = File::create(&part).await?;
             io::copy(&mut data.as_slice(), &mut file).await?;
             file.sync_data().await?;
-            Ok(())
-        } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                "Download failed",
-            )))
+            drop(file);
+            set_executable(&part).await;
+            fs::rename(&part, path).await
+        }
+        .await;
+        if result.is_err() {
+            let _ = fs::remove_file(&part).await;
         }
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_filename(url: &str) -> Option<String> {
+        Download::new(url, Vec::new()).get_filename().ok()
+    }
+
+    #[test]
+    fn filename_is_the_last_segment() {
+        assert_eq!(get_filename("https://example.com/files/bin.sh").as_deref(), Some("bin.sh"));
+        assert_eq!(get_filename("https://example.com/seeds.jar?token=abc").as_deref(), Some("seeds.jar"));
+        // Dot segments are resolved by the URL parser before the name is taken
+        assert_eq!(get_filename("https://example.com/files/../bin.sh").as_deref(), Some("bin.sh"));
+    }
+
+    #[test]
+    fn unsafe_filenames_are_rejected() {
+        assert_eq!(get_filename("https://example.com/"), None);
+        assert_eq!(get_filename("https://example.com/files/"), None);
+        assert_eq!(get_filename("https://example.com/files/.."), None);
+        assert_eq!(get_filename("https://example.com/files/%2e%2e"), None);
+        assert_eq!(get_filename("https://example.com/files/..%2Fbin.sh"), None);
+        assert_eq!(get_filename("https://example.com/files/%2Fetc%2Fpasswd"), None);
+        assert_eq!(get_filename("https://example.com/files/bin%00.sh"), None);
+        assert_eq!(get_filename("not a url"), None);
     }
 }
@@ -1,16 +1,22 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
 use crate::manager::platform::Platform;
 
-use super::download::Download;
+use super::download::{Download, Progress};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Project {
     pub id: i64,
     pub name: String,
     pub platforms: HashMap<i64, ProjectPlatform>,
+    /// Number of cores each task uses.
+    pub cores: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProjectPlatform {
     pub platform: Platform,
     pub binary: Download,
@@ -23,12 +29,61 @@ impl Project {
             id,
             name: name.to_string(),
             platforms: HashMap::new(),
+            cores: 1,
+        }
+    }
+
+    /// Returns the directory holding the project's binaries and inputs.
+    ///
+    /// The directory is keyed by id rather than name, so it's always a safe path component
+    /// and survives the project being renamed.
+    pub fn get_dir(&self, data_dir: &Path) -> PathBuf {
+        data_dir.join("projects").join(self.id.to_string())
+    }
+
+    /// Returns where the binary for `platform` is cached.
+    pub fn get_binary_path(&self, platform: &ProjectPlatform, data_dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        // Binaries of different platforms may share a file name, so keep them apart
+        Ok(self
+            .get_dir(data_dir)
+            .join("bin")
+            .join(platform.platform.id.to_string())
+            .join(platform.binary.get_filename()?))
+    }
+
+    /// Downloads the binary for `platform` unless a verified copy is already cached.
+    pub async fn download_binary(
+        &self,
+        platform: &ProjectPlatform,
+        data_dir: &Path,
+        progress: Progress<'_>,
+    ) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = self.get_binary_path(platform, data_dir)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
         }
+
+        platform.binary.download_to_file(&path, progress).await?;
+        Ok(path)
     }
 
     pub fn add_platform(&mut self, platform: ProjectPlatform) {
         self.platforms.insert(platform.platform.id, platform);
     }
+
+    /// Returns the binaries runnable on the given platforms, most preferred first.
+    ///
+    /// Binaries are ordered by priority (highest first), using the local override for
+    /// their platform if one is set. Ties are broken by platform id so the order is stable.
+    pub fn get_platforms(&self, platform_ids: &[i64], overrides: &HashMap<i64, i32>) -> Vec<&ProjectPlatform> {
+        let mut platforms = platform_ids
+            .iter()
+            .filter_map(|id| self.platforms.get(id))
+            .collect::<Vec<&ProjectPlatform>>();
+
+        platforms.sort_by_key(|p| (std::cmp::Reverse(p.get_priority(overrides)), p.platform.id));
+        platforms
+    }
 }
 
 impl ProjectPlatform {
@@ -39,4 +94,8 @@ impl ProjectPlatform {
             priority,
         }
     }
-}
\ No newline at end of file
+
+    pub fn get_priority(&self, overrides: &HashMap<i64, i32>) -> i32 {
+        overrides.get(&self.platform.id).copied().unwrap_or(self.priority)
+    }
+}
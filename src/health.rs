@@ -0,0 +1,429 @@
+//! Health of the running client, for orchestrators that can otherwise only tell that the process
+//! exists.
+//!
+//! The client is unhealthy when a worker thread died, the server has been unreachable for too
+//! long or the data directory can't be written to. It's ready when it's healthy, reaches the
+//! server and isn't draining. The checks are served over HTTP at `/healthz` and `/readyz` when
+//! `health_address` is set, and over the control endpoints.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use simplelog::{error, info};
+use tokio::sync::watch;
+
+use crate::config::ConfigHandle;
+use crate::manager::batch::Batch;
+use crate::manager::status::{Activity, StatusBoard};
+use crate::manager::worker::WorkerContext;
+
+/// Name of the file written to the data directory to check that it's writable.
+const PROBE_FILE: &str = ".health-probe";
+
+/// Settings of the health checks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSettings {
+    /// Seconds the server may be unreachable for before the client is unhealthy.
+    pub max_unreachable: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> HealthSettings {
+        HealthSettings { max_unreachable: 900 }
+    }
+}
+
+/// The result of the health checks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Whether the client works, and needn't be restarted.
+    pub healthy: bool,
+    /// Whether the client is healthy and able to take tasks.
+    pub ready: bool,
+    /// Ids of the worker threads that died.
+    pub dead_workers: Vec<i32>,
+    /// Seconds since the server became unreachable, if it is.
+    pub unreachable: Option<u64>,
+    /// Why the data directory can't be written to, if it can't.
+    pub data_dir_error: Option<String>,
+    pub draining: bool,
+    /// What makes the client unhealthy or not ready.
+    pub problems: Vec<String>,
+}
+
+/// Since when and why the server has been unreachable.
+#[derive(Debug, Clone)]
+pub struct Outage {
+    pub since: Instant,
+    pub error: String,
+}
+
+/// Whether the server answers, updated by every request of the API client.
+///
+/// Requests that can't connect, time out or get a server error count as failures. Any other
+/// response, even a rejection, shows the server is up.
+#[derive(Debug, Clone)]
+pub struct Reachability {
+    outage: Arc<watch::Sender<Option<Outage>>>,
+}
+
+impl Reachability {
+    pub fn new() -> Reachability {
+        let (outage, _) = watch::channel(None);
+        Reachability { outage: Arc::new(outage) }
+    }
+
+    /// Notes that the server answered a request.
+    pub fn reached(&self) {
+        if self.outage.borrow().is_some() {
+            self.outage.send_replace(None);
+        }
+    }
+
+    /// Notes that a request failed, which only counts if the server didn't answer or failed itself.
+    pub fn failed(&self, err: &reqwest::Error) {
+        let server_error = err.status().map(|status| status.is_server_error()).unwrap_or(false);
+        if !(err.is_connect() || err.is_timeout() || err.is_request() || server_error) {
+            return self.reached();
+        }
+        if self.outage.borrow().is_none() {
+            self.outage.send_replace(Some(Outage {
+                since: Instant::now(),
+                error: err.to_string(),
+            }));
+        }
+    }
+
+    /// Returns how long the server has been unreachable for, if it is.
+    pub fn get_unreachable_for(&self) -> Option<Duration> {
+        self.outage.borrow().as_ref().map(|outage| outage.since.elapsed())
+    }
+
+    /// Returns a receiver notified when the server becomes unreachable or reachable again.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Outage>> {
+        self.outage.subscribe()
+    }
+}
+
+impl Default for Reachability {
+    fn default() -> Reachability {
+        Reachability::new()
+    }
+}
+
+/// Checks the health of the client, shared by the endpoints reporting it.
+#[derive(Clone)]
+pub struct HealthChecker {
+    config: ConfigHandle,
+    status: StatusBoard,
+    batch: Batch,
+    reachability: Reachability,
+}
+
+impl HealthChecker {
+    pub fn new(context: &WorkerContext) -> HealthChecker {
+        HealthChecker {
+            config: context.config.clone(),
+            status: context.status.clone(),
+            batch: context.batch.clone(),
+            reachability: context.api.get_reachability().clone(),
+        }
+    }
+
+    pub async fn check(&self) -> HealthReport {
+        let config = self.config.get();
+        let mut problems = Vec::new();
+
+        let dead_workers = self
+            .status
+            .get_workers()
+            .into_iter()
+            .filter_map(|worker| match worker.activity {
+                Activity::Dead { error } => {
+                    problems.push(format!("worker thread #{} died: {}", worker.id, error));
+                    Some(worker.id)
+                }
+                _ => None,
+            })
+            .collect::<Vec<i32>>();
+
+        let unreachable = self.reachability.get_unreachable_for().map(|elapsed| elapsed.as_secs());
+        let unreachable_too_long = match unreachable {
+            Some(unreachable) => {
+                let elapsed = humantime::format_duration(Duration::from_secs(unreachable));
+                problems.push(format!("the server has been unreachable for {}", elapsed));
+                unreachable > config.health.max_unreachable
+            }
+            None => false,
+        };
+
+        let data_dir = config.data_dir.clone();
+        let data_dir_error = match tokio::task::spawn_blocking(move || probe(&data_dir)).await {
+            Ok(result) => result.err(),
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(err) = &data_dir_error {
+            problems.push(format!("the data directory isn't writable: {}", err));
+        }
+
+        let draining = self.batch.is_stopped();
+        if draining {
+            problems.push("the client is draining".to_string());
+        }
+
+        let healthy = dead_workers.is_empty() && !unreachable_too_long && data_dir_error.is_none();
+        HealthReport {
+            healthy,
+            ready: healthy && unreachable.is_none() && !draining,
+            dead_workers,
+            unreachable,
+            data_dir_error,
+            draining,
+            problems,
+        }
+    }
+}
+
+/// Writes and removes a file in the data directory.
+fn probe(data_dir: &Path) -> Result<(), String> {
+    let path = data_dir.join(PROBE_FILE);
+    std::fs::write(&path, b"ok").map_err(|e| e.to_string())?;
+    std::fs::remove_file(&path).map_err(|e| e.to_string())
+}
+
+impl HealthReport {
+    /// Returns whether the client passes the readiness check with `ready`, or else the liveness
+    /// check.
+    pub fn passes(&self, ready: bool) -> bool {
+        if ready {
+            self.ready
+        } else {
+            self.healthy
+        }
+    }
+}
+
+/// Serves the health checks over HTTP, `GET /healthz` for liveness and `GET /readyz` for
+/// readiness, answering 503 when they fail.
+pub struct HealthServer {
+    incoming: AddrIncoming,
+}
+
+impl HealthServer {
+    pub fn bind(address: &SocketAddr) -> Result<HealthServer, Box<dyn std::error::Error>> {
+        let incoming = AddrIncoming::bind(address)
+            .map_err(|e| format!("unable to serve health checks on {}: {}", address, e))?;
+        Ok(HealthServer { incoming })
+    }
+
+    pub async fn run(self, health: HealthChecker) {
+        info!("<green><bold>Serving health checks on http://{}/healthz</>", self.incoming.local_addr());
+        let service = make_service_fn(move |_| {
+            let health = health.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let health = health.clone();
+                    async move { Ok::<_, Infallible>(handle(request, &health).await) }
+                }))
+            }
+        });
+
+        if let Err(err) = Server::builder(self.incoming).serve(service).await {
+            error!("<red>Health endpoint failed: {}</>", err);
+        }
+    }
+}
+
+async fn handle(request: Request<Body>, health: &HealthChecker) -> Response<Body> {
+    let ready = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => false,
+        (&Method::GET, "/readyz") => true,
+        _ => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+    };
+
+    let report = health.check().await;
+    let status = if report.passes(ready) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&report).unwrap_or_default()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::manager::batch::BatchLimits;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dicc-client-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn create_checker(data_dir: PathBuf, max_unreachable: u64) -> HealthChecker {
+        let config = Config {
+            data_dir,
+            health: HealthSettings { max_unreachable },
+            ..Config::default()
+        };
+        // The checks only read the config, so the sender isn't needed
+        let (_, config) = ConfigHandle::new(config);
+        HealthChecker {
+            config,
+            status: StatusBoard::new(),
+            batch: Batch::new(BatchLimits::default()),
+            reachability: Reachability::new(),
+        }
+    }
+
+    fn set_unreachable_for(reachability: &Reachability, elapsed: Duration) {
+        reachability.outage.send_replace(Some(Outage {
+            since: Instant::now() - elapsed,
+            error: "connection refused".to_string(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn healthy_client_is_ready() {
+        let checker = create_checker(temp_dir("health-ok"), 900);
+        checker.status.register(0, "default");
+        let report = checker.check().await;
+        assert!(report.healthy && report.ready, "{:?}", report.problems);
+        assert!(report.problems.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dead_workers_are_unhealthy() {
+        let checker = create_checker(temp_dir("health-dead"), 900);
+        checker.status.register(0, "default");
+        checker.status.register(1, "default").set(Activity::Dead {
+            error: "panicked".to_string(),
+        });
+
+        let report = checker.check().await;
+        assert!(!report.healthy && !report.ready);
+        assert_eq!(report.dead_workers, vec![1]);
+        assert!(!report.passes(false) && !report.passes(true));
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_unhealthy_after_max_unreachable() {
+        let checker = create_checker(temp_dir("health-unreachable"), 60);
+        set_unreachable_for(&checker.reachability, Duration::from_secs(10));
+        let report = checker.check().await;
+        assert!(report.healthy && !report.ready);
+        assert_eq!(report.unreachable, Some(10));
+
+        set_unreachable_for(&checker.reachability, Duration::from_secs(61));
+        let report = checker.check().await;
+        assert!(!report.healthy && !report.ready);
+
+        checker.reachability.reached();
+        let report = checker.check().await;
+        assert!(report.healthy && report.ready);
+        assert_eq!(report.unreachable, None);
+    }
+
+    #[tokio::test]
+    async fn unwritable_data_dir_is_unhealthy() {
+        let checker = create_checker(temp_dir("health-probe").join("missing"), 900);
+        let report = checker.check().await;
+        assert!(!report.healthy && !report.ready);
+        assert!(report.data_dir_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn draining_client_is_healthy_but_not_ready() {
+        let checker = create_checker(temp_dir("health-drain"), 900);
+        checker.batch.drain();
+        let report = checker.check().await;
+        assert!(report.healthy && !report.ready && report.draining);
+        assert!(report.passes(false) && !report.passes(true));
+    }
+
+    /// Starts a server answering `/down` with 503, `/denied` with 401 and `/slow` after a second.
+    fn start_server() -> SocketAddr {
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                let status = match request.uri().path() {
+                    "/down" => StatusCode::SERVICE_UNAVAILABLE,
+                    "/denied" => StatusCode::UNAUTHORIZED,
+                    _ => {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        StatusCode::OK
+                    }
+                };
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = status;
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        address
+    }
+
+    async fn get_error(url: &str) -> reqwest::Error {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        match client.get(url).send().await {
+            Ok(response) => response.error_for_status().unwrap_err(),
+            Err(err) => err,
+        }
+    }
+
+    #[tokio::test]
+    async fn reachability_counts_only_server_failures() {
+        let address = start_server();
+        let reachability = Reachability::new();
+        let mut outages = reachability.subscribe();
+
+        // Nothing listens on the port of a listener that was just dropped
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        reachability.failed(&get_error(&format!("http://{}/", closed)).await);
+        assert!(reachability.get_unreachable_for().is_some());
+        assert!(outages.has_changed().unwrap());
+        outages.borrow_and_update();
+
+        // A rejection means the server is up
+        reachability.failed(&get_error(&format!("http://{}/denied", address)).await);
+        assert!(reachability.get_unreachable_for().is_none());
+        assert!(outages.has_changed().unwrap());
+        outages.borrow_and_update();
+
+        reachability.failed(&get_error(&format!("http://{}/down", address)).await);
+        assert!(reachability.get_unreachable_for().is_some());
+        reachability.reached();
+        reachability.failed(&get_error(&format!("http://{}/slow", address)).await);
+        assert!(reachability.get_unreachable_for().is_some());
+
+        // Further failures keep the start of the outage
+        let since = reachability.outage.borrow().as_ref().unwrap().since;
+        reachability.failed(&get_error(&format!("http://{}/down", address)).await);
+        assert_eq!(reachability.outage.borrow().as_ref().unwrap().since, since);
+    }
+}
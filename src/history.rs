@@ -0,0 +1,391 @@
+//! Record of every assignment the client worked on, kept in a SQLite database in the data
+//! directory.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use rusqlite::{params, Connection, Row, ToSql};
+use serde::{Deserialize, Serialize};
+use simplelog::error;
+
+use crate::data::assignment::AssignmentResult;
+
+/// Name of the database in the data directory.
+pub const HISTORY_FILE: &str = "history.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS assignments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    assignment_id INTEGER NOT NULL,
+    project_id INTEGER NOT NULL,
+    project TEXT NOT NULL,
+    platform TEXT,
+    binary_checksum TEXT,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    wall_time REAL,
+    cpu_time REAL,
+    exit_code INTEGER,
+    output_size INTEGER,
+    error_size INTEGER,
+    outcome TEXT NOT NULL,
+    submission_id INTEGER,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS assignments_finished_at ON assignments (finished_at);
+CREATE INDEX IF NOT EXISTS assignments_project_id ON assignments (project_id);
+";
+
+const COLUMNS: &str = "assignment_id, project_id, project, platform, binary_checksum, started_at, finished_at, \
+    wall_time, cpu_time, exit_code, output_size, error_size, outcome, submission_id, error";
+
+/// How an assignment ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The result was computed and submitted.
+    Succeeded,
+    /// No binary could compute the result, or it couldn't be submitted.
+    Failed,
+    /// The binary was killed after the pool's timeout.
+    TimedOut,
+}
+
+/// An assignment the client is done with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub assignment_id: i64,
+    pub project_id: i64,
+    pub project: String,
+    /// Platform of the binary that produced the result.
+    pub platform: Option<String>,
+    /// Checksum of that binary, e.g. `sha256:...`.
+    pub binary_checksum: Option<String>,
+    /// RFC 3339 time the assignment was started at.
+    pub started_at: String,
+    /// RFC 3339 time the assignment was finished at.
+    pub finished_at: String,
+    /// Seconds the binary ran for.
+    pub wall_time: Option<f64>,
+    /// Seconds of CPU time used by the binary, where the OS reports it.
+    pub cpu_time: Option<f64>,
+    pub exit_code: Option<i32>,
+    /// Size of the binary's standard output, in bytes.
+    pub output_size: Option<u64>,
+    /// Size of the binary's standard error, in bytes.
+    pub error_size: Option<u64>,
+    pub outcome: Outcome,
+    /// Id the server gave the submitted result.
+    pub submission_id: Option<i64>,
+    /// Why the assignment failed.
+    pub error: Option<String>,
+}
+
+/// Which entries to return from the history.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    /// Project id or name.
+    pub project: Option<String>,
+    pub outcome: Option<Outcome>,
+    /// Only entries finished at or after this time.
+    pub since: Option<SystemTime>,
+    /// Only entries finished before this time.
+    pub until: Option<SystemTime>,
+    /// Return at most this many entries, the most recent ones.
+    pub limit: Option<usize>,
+}
+
+/// The assignment history, shared by all worker threads.
+#[derive(Clone)]
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl History {
+    /// Opens the database, creating it if needed.
+    pub fn open(path: &Path) -> Result<History, Box<dyn std::error::Error>> {
+        let connection =
+            Connection::open(path).map_err(|e| format!("unable to open history {}: {}", path.display(), e))?;
+
+        // The history command reads the database while the client writes to it
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(History {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Records a finished assignment, logging rather than failing if it can't.
+    pub fn record(&self, entry: &HistoryEntry) {
+        if let Err(err) = self.insert(entry) {
+            error!(
+                "<red>Unable to record assignment {} in the history: {}</>",
+                entry.assignment_id, err
+            );
+        }
+    }
+
+    fn insert(&self, entry: &HistoryEntry) -> rusqlite::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            &format!(
+                "INSERT INTO assignments ({}) VALUES ({})",
+                COLUMNS,
+                (1..=15).map(|i| format!("?{}", i)).collect::<Vec<String>>().join(", ")
+            ),
+            params![
+                entry.assignment_id,
+                entry.project_id,
+                entry.project,
+                entry.platform,
+                entry.binary_checksum,
+                entry.started_at,
+                entry.finished_at,
+                entry.wall_time,
+                entry.cpu_time,
+                entry.exit_code,
+                entry.output_size,
+                entry.error_size,
+                entry.outcome.get_name(),
+                entry.submission_id,
+                entry.error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the entries matching `filter`, the oldest first.
+    pub fn query(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(project) = &filter.project {
+            conditions.push("(CAST(project_id AS TEXT) = ? OR project = ?)");
+            values.push(Box::new(project.clone()));
+            values.push(Box::new(project.clone()));
+        }
+        if let Some(outcome) = filter.outcome {
+            conditions.push("outcome = ?");
+            values.push(Box::new(outcome.get_name()));
+        }
+        if let Some(since) = filter.since {
+            conditions.push("finished_at >= ?");
+            values.push(Box::new(format_time(since)));
+        }
+        if let Some(until) = filter.until {
+            conditions.push("finished_at < ?");
+            values.push(Box::new(format_time(until)));
+        }
+
+        let mut sql = format!("SELECT {} FROM assignments", COLUMNS);
+        if !conditions.is_empty() {
+            sql += &format!(" WHERE {}", conditions.join(" AND "));
+        }
+        sql += " ORDER BY finished_at DESC, id DESC";
+        if let Some(limit) = filter.limit {
+            sql += &format!(" LIMIT {}", limit);
+        }
+
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&sql)?;
+        let mut entries = statement
+            .query_map(rusqlite::params_from_iter(values.iter()), read_entry)?
+            .collect::<Result<Vec<HistoryEntry>, _>>()?;
+        entries.reverse();
+        Ok(entries)
+    }
+}
+
+impl HistoryEntry {
+    /// Creates an entry for an assignment finished now, without the details of a binary run.
+    pub fn new(
+        assignment_id: i64,
+        project_id: i64,
+        project: &str,
+        started: SystemTime,
+        outcome: Outcome,
+    ) -> HistoryEntry {
+        HistoryEntry {
+            assignment_id,
+            project_id,
+            project: project.to_string(),
+            platform: None,
+            binary_checksum: None,
+            started_at: format_time(started),
+            finished_at: format_time(SystemTime::now()),
+            wall_time: None,
+            cpu_time: None,
+            exit_code: None,
+            output_size: None,
+            error_size: None,
+            outcome,
+            submission_id: None,
+            error: None,
+        }
+    }
+
+    /// Returns the seconds from the start of the assignment to its end.
+    pub fn get_elapsed(&self) -> Option<f64> {
+        let started = humantime::parse_rfc3339(&self.started_at).ok()?;
+        let finished = humantime::parse_rfc3339(&self.finished_at).ok()?;
+        finished.duration_since(started).ok().map(|elapsed| elapsed.as_secs_f64())
+    }
+
+    /// Fills in the details of the binary run that produced `result`.
+    pub fn set_result(&mut self, result: &AssignmentResult) {
+        self.platform = result.platform.clone();
+        self.binary_checksum = result.checksum.clone();
+        self.wall_time = Some(Duration::from_nanos(result.execution_time as u64).as_secs_f64());
+        self.cpu_time = result.cpu_time.map(|time| time.as_secs_f64());
+        self.exit_code = Some(result.status);
+        self.output_size = Some(result.output.len() as u64);
+        self.error_size = Some(result.error.len() as u64);
+    }
+}
+
+impl Outcome {
+    /// Returns the name of the outcome, as stored and serialized.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+            Outcome::TimedOut => "timed_out",
+        }
+    }
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Outcome, String> {
+        match s {
+            "succeeded" => Ok(Outcome::Succeeded),
+            "failed" => Ok(Outcome::Failed),
+            "timed_out" => Ok(Outcome::TimedOut),
+            _ => Err(format!("invalid outcome `{}`, expected succeeded, failed or timed_out", s)),
+        }
+    }
+}
+
+fn read_entry(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    let outcome = row.get::<_, String>(12)?;
+    Ok(HistoryEntry {
+        assignment_id: row.get(0)?,
+        project_id: row.get(1)?,
+        project: row.get(2)?,
+        platform: row.get(3)?,
+        binary_checksum: row.get(4)?,
+        started_at: row.get(5)?,
+        finished_at: row.get(6)?,
+        wall_time: row.get(7)?,
+        cpu_time: row.get(8)?,
+        exit_code: row.get(9)?,
+        output_size: row.get(10)?,
+        error_size: row.get(11)?,
+        outcome: outcome.parse().unwrap_or(Outcome::Failed),
+        submission_id: row.get(13)?,
+        error: row.get(14)?,
+    })
+}
+
+/// Formats a time as stored, RFC 3339 in UTC so that times sort as text.
+fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(name: &str) -> History {
+        let path = std::env::temp_dir().join(format!("dicc-client-{}-{}.db", name, std::process::id()));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        History::open(&path).unwrap()
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000 + seconds)
+    }
+
+    fn entry(assignment_id: i64, project_id: i64, project: &str, finished: u64, outcome: Outcome) -> HistoryEntry {
+        let mut entry = HistoryEntry::new(assignment_id, project_id, project, at(finished - 1), outcome);
+        entry.finished_at = format_time(at(finished));
+        entry
+    }
+
+    fn ids(entries: &[HistoryEntry]) -> Vec<i64> {
+        entries.iter().map(|e| e.assignment_id).collect()
+    }
+
+    #[test]
+    fn recorded_entries_are_read_back() {
+        let history = open("history-record");
+        let mut recorded = entry(1, 12, "seeds", 10, Outcome::Succeeded);
+        recorded.platform = Some("linux-avx2".to_string());
+        recorded.binary_checksum = Some("sha256:abc".to_string());
+        recorded.wall_time = Some(1.5);
+        recorded.exit_code = Some(0);
+        recorded.output_size = Some(42);
+        recorded.submission_id = Some(7);
+        history.record(&recorded);
+
+        let entries = history.query(&HistoryFilter::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        let read = &entries[0];
+        assert_eq!((read.assignment_id, read.project_id, read.project.as_str()), (1, 12, "seeds"));
+        assert_eq!(read.platform.as_deref(), Some("linux-avx2"));
+        assert_eq!(read.binary_checksum.as_deref(), Some("sha256:abc"));
+        assert_eq!(read.wall_time, Some(1.5));
+        assert_eq!(read.exit_code, Some(0));
+        assert_eq!(read.output_size, Some(42));
+        assert_eq!(read.submission_id, Some(7));
+        assert_eq!(read.outcome, Outcome::Succeeded);
+        assert_eq!(read.get_elapsed(), Some(1.0));
+    }
+
+    #[test]
+    fn query_filters_entries() {
+        let history = open("history-query");
+        history.record(&entry(1, 12, "seeds", 10, Outcome::Succeeded));
+        history.record(&entry(2, 13, "multi", 20, Outcome::Failed));
+        history.record(&entry(3, 12, "seeds", 30, Outcome::TimedOut));
+        history.record(&entry(4, 12, "seeds", 40, Outcome::Succeeded));
+
+        let query = |filter: HistoryFilter| ids(&history.query(&filter).unwrap());
+        assert_eq!(query(HistoryFilter::default()), vec![1, 2, 3, 4]);
+
+        // Projects match by id or name
+        let by_project = |project: &str| HistoryFilter {
+            project: Some(project.to_string()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(query(by_project("12")), vec![1, 3, 4]);
+        assert_eq!(query(by_project("multi")), vec![2]);
+        assert_eq!(query(by_project("unknown")), Vec::<i64>::new());
+
+        let outcome = HistoryFilter {
+            outcome: Some(Outcome::Succeeded),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(query(outcome), vec![1, 4]);
+
+        // Since is inclusive and until exclusive
+        let range = HistoryFilter {
+            since: Some(at(20)),
+            until: Some(at(40)),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(query(range), vec![2, 3]);
+
+        // The limit keeps the most recent entries, still returned oldest first
+        let limited = HistoryFilter {
+            project: Some("seeds".to_string()),
+            limit: Some(2),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(query(limited), vec![3, 4]);
+    }
+}
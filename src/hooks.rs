@@ -0,0 +1,413 @@
+//! User hooks fired on lifecycle events, either a local command receiving the event as JSON on
+//! stdin or a URL the event is POSTed to.
+
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use simplelog::{debug, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+
+use crate::config::ConfigHandle;
+use crate::health::Reachability;
+use crate::history::HistoryEntry;
+
+/// Hooks running at once, further events wait for one of them to finish.
+const MAX_RUNNING: u32 = 32;
+
+/// Something that happened in the client, sent to the hooks as `{"event": "<kind>", "time": ...}`
+/// along with the fields of the event.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    AssignmentStarted {
+        assignment_id: i64,
+        project_id: i64,
+        project: String,
+    },
+    /// The result was computed and submitted.
+    AssignmentFinished { assignment: HistoryEntry },
+    /// No binary could compute the result, it timed out or couldn't be submitted.
+    AssignmentFailed { assignment: HistoryEntry },
+    /// The server published a new binary for a project, which is downloaded when next needed.
+    BinaryUpdated {
+        project_id: i64,
+        project: String,
+        platform: String,
+        checksum: Option<String>,
+    },
+    /// The platforms detected on this host changed on a catalog refresh.
+    PlatformsChanged { added: Vec<String>, removed: Vec<String> },
+    /// A request failed to reach the server after it last succeeded.
+    ServerUnreachable { error: String },
+    /// A request succeeded after the server was unreachable.
+    ServerReachable,
+}
+
+/// The kind of an [`Event`], used to choose which hooks it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    AssignmentStarted,
+    AssignmentFinished,
+    AssignmentFailed,
+    BinaryUpdated,
+    PlatformsChanged,
+    ServerUnreachable,
+    ServerReachable,
+}
+
+/// A hook, with either `command` or `url` set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookSettings {
+    /// Events that fire the hook, every event if empty.
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Command run with the event as JSON on stdin, e.g. `["/usr/local/bin/notify", "--quiet"]`.
+    pub command: Option<Vec<String>>,
+    /// URL the event is POSTed to as JSON.
+    pub url: Option<String>,
+    /// Seconds after which the command is killed or the request abandoned.
+    #[serde(default = "HookSettings::default_timeout")]
+    pub timeout: u64,
+}
+
+impl HookSettings {
+    fn default_timeout() -> u64 {
+        30
+    }
+
+    /// Checks that the hook does exactly one thing.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match (&self.command, &self.url) {
+            (Some(command), None) if command.is_empty() => Err("hook command must not be empty".into()),
+            (Some(_), None) => Ok(()),
+            (None, Some(url)) => {
+                reqwest::Url::parse(url).map_err(|e| format!("invalid hook url `{}`: {}", url, e))?;
+                Ok(())
+            }
+            _ => Err("hooks must set either command or url".into()),
+        }
+    }
+
+    fn fires_on(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+impl Event {
+    pub fn get_kind(&self) -> EventKind {
+        match self {
+            Event::AssignmentStarted { .. } => EventKind::AssignmentStarted,
+            Event::AssignmentFinished { .. } => EventKind::AssignmentFinished,
+            Event::AssignmentFailed { .. } => EventKind::AssignmentFailed,
+            Event::BinaryUpdated { .. } => EventKind::BinaryUpdated,
+            Event::PlatformsChanged { .. } => EventKind::PlatformsChanged,
+            Event::ServerUnreachable { .. } => EventKind::ServerUnreachable,
+            Event::ServerReachable => EventKind::ServerReachable,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    /// RFC 3339 time the event happened at.
+    time: String,
+}
+
+/// Sends events to the [`HookRunner`], shared by everything that fires them.
+#[derive(Clone)]
+pub struct Hooks {
+    sender: mpsc::UnboundedSender<(Event, SystemTime)>,
+}
+
+impl Hooks {
+    /// Fires the hooks listening for `event`, without waiting for them.
+    pub fn fire(&self, event: Event) {
+        // Nothing listens once the client is shutting down
+        let _ = self.sender.send((event, SystemTime::now()));
+    }
+
+    /// Fires [`Event::ServerUnreachable`] and [`Event::ServerReachable`] as the server goes down
+    /// and comes back.
+    pub async fn watch_server(&self, reachability: &Reachability) {
+        let mut receiver = reachability.subscribe();
+        let mut unreachable = receiver.borrow().is_some();
+        while receiver.changed().await.is_ok() {
+            let error = receiver.borrow().as_ref().map(|outage| outage.error.clone());
+            match error {
+                Some(error) if !unreachable => {
+                    unreachable = true;
+                    self.fire(Event::ServerUnreachable { error });
+                }
+                None if unreachable => {
+                    unreachable = false;
+                    self.fire(Event::ServerReachable);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Runs the configured hooks for the events fired through its [`Hooks`].
+pub struct HookRunner {
+    config: ConfigHandle,
+    client: reqwest::Client,
+    receiver: Mutex<mpsc::UnboundedReceiver<(Event, SystemTime)>>,
+    running: Arc<Semaphore>,
+}
+
+impl HookRunner {
+    pub fn new(config: &ConfigHandle) -> (HookRunner, Hooks) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let runner = HookRunner {
+            config: config.clone(),
+            client: reqwest::Client::new(),
+            receiver: Mutex::new(receiver),
+            running: Arc::new(Semaphore::new(MAX_RUNNING as usize)),
+        };
+        let hooks = Hooks { sender };
+        (runner, hooks)
+    }
+
+    /// Runs hooks until every [`Hooks`] is dropped. Hooks run concurrently, so a slow one doesn't
+    /// hold up the others.
+    pub async fn run(&self) {
+        let mut receiver = self.receiver.lock().await;
+        while let Some((event, time)) = receiver.recv().await {
+            self.dispatch(event, time).await;
+        }
+    }
+
+    /// Runs the hooks of the events fired so far and waits up to `timeout` for every running hook,
+    /// so the last events aren't lost when the client exits.
+    pub async fn finish(&self, timeout: Duration) {
+        let wait = async {
+            let mut receiver = self.receiver.lock().await;
+            while let Ok((event, time)) = receiver.try_recv() {
+                self.dispatch(event, time).await;
+            }
+            let _ = self.running.acquire_many(MAX_RUNNING).await;
+        };
+        if tokio::time::timeout(timeout, wait).await.is_err() {
+            warn!("<yellow>Exiting without waiting for the remaining hooks</>");
+        }
+    }
+
+    async fn dispatch(&self, event: Event, time: SystemTime) {
+        let kind = event.get_kind();
+        let payload = Payload {
+            event: &event,
+            time: humantime::format_rfc3339_millis(time).to_string(),
+        };
+        let payload = match serde_json::to_vec(&payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("<yellow>Unable to serialize {} event: {}</>", kind.get_name(), err);
+                return;
+            }
+        };
+
+        // Hooks are read for every event so config reloads apply right away
+        for hook in self.config.get().hooks.iter().filter(|hook| hook.fires_on(kind)) {
+            let permit = match self.running.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return,
+            };
+            let hook = hook.clone();
+            let client = self.client.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                if let Err(err) = run_hook(&hook, &client, kind, payload).await {
+                    warn!("<yellow>Hook for {} failed: {}</>", kind.get_name(), err);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+async fn run_hook(
+    hook: &HookSettings,
+    client: &reqwest::Client,
+    kind: EventKind,
+    payload: Vec<u8>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let timeout = Duration::from_secs(hook.timeout);
+    if let Some(url) = &hook.url {
+        let response = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload)
+            .timeout(timeout)
+            .send()
+            .await?;
+        response.error_for_status()?;
+        return Ok(());
+    }
+
+    let command = match &hook.command {
+        Some(command) if !command.is_empty() => command,
+        _ => return Ok(()),
+    };
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .env("DICC_EVENT", kind.get_name())
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("unable to run {}: {}", command[0], e))?;
+
+    let run = async {
+        if let Some(mut stdin) = child.stdin.take() {
+            // The hook may not read the event at all
+            let _ = stdin.write_all(&payload).await;
+        }
+        child.wait().await
+    };
+    let status = tokio::time::timeout(timeout, run)
+        .await
+        .map_err(|_| format!("{} timed out after {}s", command[0], hook.timeout))??;
+    if !status.success() {
+        return Err(format!("{} exited with {}", command[0], status).into());
+    }
+    debug!("Ran hook {} for {}", command[0], kind.get_name());
+    Ok(())
+}
+
+impl EventKind {
+    /// Returns the name of the event, as sent to hooks and in `DICC_EVENT`.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            EventKind::AssignmentStarted => "assignment_started",
+            EventKind::AssignmentFinished => "assignment_finished",
+            EventKind::AssignmentFailed => "assignment_failed",
+            EventKind::BinaryUpdated => "binary_updated",
+            EventKind::PlatformsChanged => "platforms_changed",
+            EventKind::ServerUnreachable => "server_unreachable",
+            EventKind::ServerReachable => "server_reachable",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_hook(script: &str) -> HookSettings {
+        HookSettings {
+            events: Vec::new(),
+            command: Some(vec!["sh".to_string(), "-c".to_string(), script.to_string()]),
+            url: None,
+            timeout: 5,
+        }
+    }
+
+    #[test]
+    fn validate_requires_either_command_or_url() {
+        let hook = command_hook("true");
+        assert!(hook.validate().is_ok());
+        let url = HookSettings {
+            command: None,
+            url: Some("https://example.com/hook".to_string()),
+            ..hook.clone()
+        };
+        assert!(url.validate().is_ok());
+
+        let invalid = [
+            HookSettings {
+                command: Some(Vec::new()),
+                ..hook.clone()
+            },
+            HookSettings {
+                command: None,
+                url: Some("not a url".to_string()),
+                ..hook.clone()
+            },
+            HookSettings {
+                url: url.url.clone(),
+                ..hook.clone()
+            },
+            HookSettings {
+                command: None,
+                ..hook.clone()
+            },
+        ];
+        for hook in invalid {
+            assert!(hook.validate().is_err(), "{:?}", hook);
+        }
+    }
+
+    #[test]
+    fn hooks_fire_on_their_events() {
+        let hook = toml::from_str::<HookSettings>(
+            "events = [\"assignment_failed\", \"server_unreachable\"]\ncommand = [\"true\"]\n",
+        )
+        .unwrap();
+        assert_eq!(hook.timeout, 30);
+        assert!(hook.fires_on(EventKind::AssignmentFailed));
+        assert!(hook.fires_on(EventKind::ServerUnreachable));
+        assert!(!hook.fires_on(EventKind::AssignmentFinished));
+
+        // Every event without a list
+        let hook = command_hook("true");
+        assert!(hook.fires_on(EventKind::ServerReachable));
+
+        let event = Event::ServerUnreachable {
+            error: "timed out".to_string(),
+        };
+        assert_eq!(event.get_kind(), EventKind::ServerUnreachable);
+        assert!(toml::from_str::<HookSettings>("events = [\"unknown\"]\ncommand = [\"true\"]\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_receive_the_event_on_stdin() {
+        let path = std::env::temp_dir().join(format!("dicc-client-hook-{}", std::process::id()));
+        let hook = command_hook(&format!("echo \"$DICC_EVENT\" > '{}' && cat >> '{}'", path.display(), path.display()));
+        let event = Event::ServerUnreachable {
+            error: "timed out".to_string(),
+        };
+        let payload = Payload {
+            event: &event,
+            time: "2026-10-19T00:00:00.000Z".to_string(),
+        };
+        let payload = serde_json::to_vec(&payload).unwrap();
+        run_hook(&hook, &reqwest::Client::new(), event.get_kind(), payload)
+            .await
+            .unwrap();
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (name, json) = output.split_once('\n').unwrap();
+        assert_eq!(name, "server_unreachable");
+        let json = serde_json::from_str::<serde_json::Value>(json).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "event": "server_unreachable",
+                "error": "timed out",
+                "time": "2026-10-19T00:00:00.000Z",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_commands_are_reported() {
+        let client = reqwest::Client::new();
+        let kind = EventKind::ServerReachable;
+        let err = run_hook(&command_hook("exit 2"), &client, kind, Vec::new()).await.unwrap_err();
+        assert!(err.to_string().contains("exited with"), "{}", err);
+
+        let mut slow = command_hook("sleep 5");
+        slow.timeout = 0;
+        let err = run_hook(&slow, &client, kind, Vec::new()).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+    }
+}
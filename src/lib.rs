@@ -0,0 +1,40 @@
+//! Client for Minecraft@Home's distributed computing platform.
+//!
+//! The `dicc-client` binary is a thin command line interface over this crate, which can be used
+//! to embed a worker in another service or to build tooling on the same pieces:
+//!
+//! - [`MCAtHomeAPI`], the API client
+//! - [`Download`], verified downloads of detectors and binaries
+//! - [`PlatformManager`], platform detection
+//! - [`ProjectWorker`], execution of a project binary on an input
+//! - [`Scheduler`], fair sharing of workers between projects
+//! - [`client::run`], a complete worker with pools, catalog refreshes and config reloads
+
+pub mod api;
+pub mod client;
+pub mod config;
+pub mod control;
+pub mod dashboard;
+pub mod data;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod logging;
+pub mod manager;
+pub mod metrics;
+pub mod report;
+pub mod stats;
+pub mod telemetry;
+pub mod util;
+
+pub use api::mcathome::api::MCAtHomeAPI;
+pub use config::Config;
+pub use data::assignment::{Assignment, AssignmentResult};
+pub use data::download::{Checksum, Download};
+pub use data::project::{Project, ProjectPlatform};
+pub use manager::batch::{BatchLimits, BatchSummary};
+pub use manager::catalog::Catalog;
+pub use manager::platform::{Platform, PlatformManager};
+pub use manager::pool::ResourceLimits;
+pub use manager::scheduler::{ProjectPolicy, Scheduler};
+pub use manager::worker::ProjectWorker;
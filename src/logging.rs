@@ -0,0 +1,505 @@
+//! Logging to the terminal, and optionally to rotated files in the data directory.
+//!
+//! Messages are logged with the `simplelog` macros and their color markup. The markup is only
+//! kept when writing human readable lines to a terminal.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Directory of the log files, in the data directory.
+pub const LOG_DIR: &str = "logs";
+
+/// Name of the current log file, rotated files are named `dicc-client.<time>.log`.
+const LOG_FILE: &str = "dicc-client.log";
+
+/// Number of lines kept while the terminal output is captured.
+const CAPTURED_LINES: usize = 200;
+
+static LOGGER: Lazy<Logger> = Lazy::new(|| Logger {
+    state: Mutex::new(LoggerState {
+        format: LogFormat::Human,
+        color_stdout: use_color(io::stdout().is_terminal()),
+        color_stderr: use_color(io::stderr().is_terminal()),
+        file: None,
+        captured: None,
+    }),
+});
+
+tokio::task_local! {
+    static FIELDS: LogFields;
+}
+
+/// How log records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// A line of text per record, colored on terminals.
+    Human,
+    /// A JSON object per line, with the record's fields.
+    Json,
+}
+
+/// When the log file is rotated, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+/// Settings of the log files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogFileSettings {
+    /// Also write the logs to `logs/dicc-client.log` in the data directory.
+    pub enabled: bool,
+    /// Size in MiB after which the log file is rotated, 0 to only rotate on time.
+    pub max_size: u64,
+    /// Rotate the log file `hourly`, `daily` or `never`.
+    pub rotation: LogRotation,
+    /// Number of rotated log files to keep.
+    pub keep: usize,
+}
+
+impl Default for LogFileSettings {
+    fn default() -> LogFileSettings {
+        LogFileSettings {
+            enabled: false,
+            max_size: 10,
+            rotation: LogRotation::Daily,
+            keep: 7,
+        }
+    }
+}
+
+/// Fields attached to the records logged by a task, see [`scope`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LogFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignment_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<i64>,
+}
+
+/// Runs `future` with `fields` attached to everything it logs.
+pub async fn scope<F: Future>(fields: LogFields, future: F) -> F::Output {
+    FIELDS.scope(fields, future).await
+}
+
+/// Returns the fields attached to the current task, to extend them in a nested [`scope`].
+pub fn get_fields() -> LogFields {
+    FIELDS.try_with(|fields| *fields).unwrap_or_default()
+}
+
+/// Installs the client's logger, writing human readable lines to the terminal until configured
+/// otherwise.
+pub fn init() -> Result<(), SetLoggerError> {
+    log::set_logger(&*LOGGER)?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
+
+/// Sets the format of the terminal and file output.
+pub fn set_format(format: LogFormat) {
+    LOGGER.state.lock().unwrap().format = format;
+}
+
+/// Stops writing to the terminal while it's used for something else, keeping the last lines for
+/// [`get_captured`] instead. Writing to the terminal resumes once called with `false`.
+pub fn set_capture(capture: bool) {
+    let mut state = LOGGER.state.lock().unwrap();
+    state.captured = if capture { Some(VecDeque::new()) } else { None };
+}
+
+/// Returns the lines logged since the terminal output was captured, the most recent last.
+pub fn get_captured() -> Vec<String> {
+    match &LOGGER.state.lock().unwrap().captured {
+        Some(captured) => captured.iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Starts writing the logs to a file in `dir` as well, rotated according to `settings`.
+///
+/// Has no effect on the output unless the logger was installed with [`init`].
+pub fn open_file(dir: &Path, settings: &LogFileSettings) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("unable to create log directory {}: {}", dir.display(), e))?;
+    let file = LogFile::open(dir, settings)
+        .map_err(|e| format!("unable to open log file in {}: {}", dir.display(), e))?;
+    LOGGER.state.lock().unwrap().file = Some(file);
+    Ok(())
+}
+
+struct Logger {
+    state: Mutex<LoggerState>,
+}
+
+struct LoggerState {
+    format: LogFormat,
+    color_stdout: bool,
+    color_stderr: bool,
+    file: Option<LogFile>,
+    /// The last lines, kept instead of writing to the terminal.
+    captured: Option<VecDeque<String>>,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: &'a str,
+    level: &'a str,
+    target: &'a str,
+    message: &'a str,
+    #[serde(flatten)]
+    fields: LogFields,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let now = SystemTime::now();
+        let time = humantime::format_rfc3339_millis(now).to_string();
+        let message = record.args().to_string();
+        let fields = get_fields();
+
+        let mut state = self.state.lock().unwrap();
+        let to_stderr = record.level() <= Level::Warn;
+        let color = if to_stderr { state.color_stderr } else { state.color_stdout };
+
+        let (line, plain) = match state.format {
+            LogFormat::Human => (
+                format_human(record.level(), &time, &message, color),
+                format_human(record.level(), &time, &message, false),
+            ),
+            // Blank lines only space out the terminal output
+            LogFormat::Json if message.trim().is_empty() => return,
+            LogFormat::Json => {
+                let line = format_json(record, &time, &message, fields);
+                (line.clone(), line)
+            }
+        };
+
+        // Logging must never bring the client down, so write errors are ignored
+        if let Some(captured) = &mut state.captured {
+            if captured.len() == CAPTURED_LINES {
+                captured.pop_front();
+            }
+            captured.push_back(format_human(record.level(), &time, &message, false).trim_end().to_string());
+        } else if to_stderr {
+            let _ = io::stderr().write_all(line.as_bytes());
+        } else {
+            let _ = io::stdout().write_all(line.as_bytes());
+        }
+        if let Some(file) = &mut state.file {
+            let _ = file.write(&plain, &time);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        if let Some(file) = &mut self.state.lock().unwrap().file {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Formats a record as `HH:MM:SS [LEVEL] message`, in UTC.
+fn format_human(level: Level, time: &str, message: &str, color: bool) -> String {
+    let time = &time[11..19];
+    if color {
+        let code = match level {
+            Level::Error => 31,
+            Level::Warn => 33,
+            Level::Info => 34,
+            Level::Debug => 36,
+            Level::Trace => 37,
+        };
+        format!("{} \x1b[{}m[{}]\x1b[0m {}\x1b[0m\n", time, code, level, message)
+    } else {
+        format!("{} [{}] {}\n", time, level, strip_colors(message))
+    }
+}
+
+fn format_json(record: &Record, time: &str, message: &str, fields: LogFields) -> String {
+    let message = strip_colors(message);
+    let record = JsonRecord {
+        time,
+        level: &record.level().as_str().to_lowercase(),
+        target: record.target(),
+        message: message.trim(),
+        fields,
+    };
+    let mut line = serde_json::to_string(&record).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// Removes the ANSI escape sequences the color markup was turned into.
+fn strip_colors(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            // Skip the parameters up to the final byte of the sequence
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    stripped
+}
+
+fn use_color(terminal: bool) -> bool {
+    terminal && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// The current log file, rotated on size and time.
+struct LogFile {
+    dir: PathBuf,
+    settings: LogFileSettings,
+    file: File,
+    size: u64,
+    /// Rotation period the file was started in.
+    period: String,
+}
+
+impl LogFile {
+    fn open(dir: &Path, settings: &LogFileSettings) -> io::Result<LogFile> {
+        let path = dir.join(LOG_FILE);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let started = humantime::format_rfc3339_seconds(metadata.modified().unwrap_or_else(|_| SystemTime::now()));
+
+        Ok(LogFile {
+            dir: dir.to_path_buf(),
+            settings: settings.clone(),
+            file,
+            size: metadata.len(),
+            period: settings.rotation.get_period(&started.to_string()).to_string(),
+        })
+    }
+
+    fn write(&mut self, line: &str, time: &str) -> io::Result<()> {
+        let max_size = self.settings.max_size * 1024 * 1024;
+        let period = self.settings.rotation.get_period(time);
+        if (max_size > 0 && self.size > 0 && self.size + line.len() as u64 > max_size) || period != self.period {
+            self.rotate(time)?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the current file aside and starts a new one, then removes the oldest rotated files.
+    fn rotate(&mut self, time: &str) -> io::Result<()> {
+        let stamp = time[..19].replace(':', "-");
+        // Number files rotated within the same second after the last one, even if older ones were pruned
+        let last = std::fs::read_dir(&self.dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                parse_rotated(&name).filter(|(s, _)| *s == stamp).map(|(_, index)| index)
+            })
+            .max();
+        let rotated = match last {
+            Some(last) => format!("dicc-client.{}.{}.log", stamp, last + 1),
+            None => format!("dicc-client.{}.log", stamp),
+        };
+        std::fs::rename(self.dir.join(LOG_FILE), self.dir.join(rotated))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(self.dir.join(LOG_FILE))?;
+        self.size = 0;
+        self.period = self.settings.rotation.get_period(time).to_string();
+
+        self.prune();
+        Ok(())
+    }
+
+    /// Removes the oldest rotated files beyond the number to keep.
+    ///
+    /// Errors are ignored, the new file is already open and the next rotation tries again.
+    fn prune(&self) {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let mut files = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let (stamp, index) = parse_rotated(&name)?;
+                Some((stamp.to_string(), index, name))
+            })
+            .collect::<Vec<(String, u32, String)>>();
+        files.sort();
+        let excess = files.len().saturating_sub(self.settings.keep);
+        for (_, _, name) in &files[..excess] {
+            let _ = std::fs::remove_file(self.dir.join(name));
+        }
+    }
+}
+
+/// Returns the stamp and index of a rotated file name, `dicc-client.STAMP[.INDEX].log`.
+///
+/// The first file of a stamp has index 0, so files sort in the order they were rotated.
+fn parse_rotated(name: &str) -> Option<(&str, u32)> {
+    let rest = name.strip_prefix("dicc-client.")?.strip_suffix(".log")?;
+    let (stamp, index) = match rest.split_once('.') {
+        Some((stamp, index)) => (stamp, index.parse::<u32>().ok()?),
+        None => (rest, 0),
+    };
+    if stamp.len() != 19 {
+        return None;
+    }
+    Some((stamp, index))
+}
+
+impl LogRotation {
+    /// Returns the part of an RFC 3339 `time` that changes when the file must be rotated.
+    fn get_period(self, time: &str) -> &str {
+        match self {
+            LogRotation::Never => "",
+            LogRotation::Hourly => &time[..13],
+            LogRotation::Daily => &time[..10],
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format `{}`, expected human or json", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dicc-client-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn list(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn strip_colors_removes_escape_sequences() {
+        assert_eq!(strip_colors("plain"), "plain");
+        assert_eq!(strip_colors("\x1b[1m\x1b[32mdone\x1b[0m in 5ms"), "done in 5ms");
+        assert_eq!(strip_colors("\x1b[38;5;208mfailed\x1b[0m"), "failed");
+        // A lone escape is dropped along with the character after it
+        assert_eq!(strip_colors("a\x1bxb"), "ab");
+        assert_eq!(strip_colors("trailing\x1b["), "trailing");
+    }
+
+    #[test]
+    fn get_period() {
+        let time = "2026-10-19T08:15:51.123Z";
+        assert_eq!(LogRotation::Never.get_period(time), "");
+        assert_eq!(LogRotation::Hourly.get_period(time), "2026-10-19T08");
+        assert_eq!(LogRotation::Daily.get_period(time), "2026-10-19");
+    }
+
+    #[test]
+    fn parse_rotated_names() {
+        assert_eq!(parse_rotated("dicc-client.2026-10-19T08-15-51.log"), Some(("2026-10-19T08-15-51", 0)));
+        assert_eq!(parse_rotated("dicc-client.2026-10-19T08-15-51.10.log"), Some(("2026-10-19T08-15-51", 10)));
+        assert_eq!(parse_rotated("dicc-client.log"), None);
+        assert_eq!(parse_rotated("dicc-client.2026-10-19T08-15-51.x.log"), None);
+        assert_eq!(parse_rotated("other.2026-10-19T08-15-51.log"), None);
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let dir = temp_dir("rotation");
+        std::fs::write(dir.join("dicc-client.2026-10-18T23-59-59.log"), "old").unwrap();
+        std::fs::write(dir.join("notes.txt"), "unrelated").unwrap();
+        let settings = LogFileSettings {
+            enabled: true,
+            max_size: 0,
+            rotation: LogRotation::Never,
+            keep: 3,
+        };
+
+        let mut file = LogFile::open(&dir, &settings).unwrap();
+        for _ in 0..12 {
+            file.write("line\n", "2026-10-19T08:15:51Z").unwrap();
+            file.rotate("2026-10-19T08:15:51Z").unwrap();
+        }
+
+        assert_eq!(
+            list(&dir),
+            vec![
+                "dicc-client.2026-10-19T08-15-51.10.log",
+                "dicc-client.2026-10-19T08-15-51.11.log",
+                "dicc-client.2026-10-19T08-15-51.9.log",
+                "dicc-client.log",
+                "notes.txt",
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_on_period_change() {
+        let dir = temp_dir("period");
+        let settings = LogFileSettings {
+            enabled: true,
+            max_size: 0,
+            rotation: LogRotation::Daily,
+            keep: 7,
+        };
+
+        let mut file = LogFile::open(&dir, &settings).unwrap();
+        file.period = "2026-10-18".to_string();
+        file.write("first\n", "2026-10-18T23:59:59Z").unwrap();
+        file.write("second\n", "2026-10-19T00:00:01Z").unwrap();
+
+        assert_eq!(list(&dir), vec!["dicc-client.2026-10-19T00-00-01.log", "dicc-client.log"]);
+        assert_eq!(std::fs::read_to_string(dir.join(LOG_FILE)).unwrap(), "second\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
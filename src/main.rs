@@ -1,94 +1,422 @@
-use std::thread;
-use std::time::Duration;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-use clap::Parser;
-use simplelog::{ColorChoice, Config, info, TerminalMode, TermLogger};
-use tokio::time::Instant;
+use clap::{Args, Parser, Subcommand};
+use simplelog::error;
 
-use crate::api::mcathome::api::MCAtHomeAPI;
-use crate::manager::worker::WorkerThread;
+use dicc_client::config::{Config, PoolSettings};
+use dicc_client::history::{HistoryFilter, Outcome};
+use dicc_client::logging::{self, LogFormat};
+use dicc_client::manager::batch::BatchLimits;
+use dicc_client::util::secret::Secret;
 
-pub mod api;
-pub mod data;
-pub mod manager;
-pub mod util;
+mod command;
 
-#[derive(Parser, Debug)]
+use command::history::HistoryFormat;
+
+#[derive(Parser, Debug, Clone)]
 #[clap(author = "Koding", version = "0.1.0", about = "DICC Client")]
 struct Opts {
-    /// Minecraft@Home API key
-    #[clap(short, long)]
-    api_key: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Config file, read after the system and user config files
+    #[clap(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Minecraft@Home API key. Visible to other users, prefer DICC_API_KEY or --api-key-file
+    #[clap(short, long, global = true)]
+    api_key: Option<String>,
+
+    /// File containing the API key, only readable by its owner, or - to read it from stdin
+    #[clap(long, global = true)]
+    api_key_file: Option<PathBuf>,
+
+    /// Base URL of the Minecraft@Home API
+    #[clap(long, global = true)]
+    base_url: Option<String>,
+
+    /// Directory under which all caches, inputs, state and logs are stored [default: $XDG_DATA_HOME/dicc-client]
+    #[clap(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Worker count of the default pool (0 for half the CPUs not used by other pools)
+    #[clap(short, long, global = true)]
+    workers: Option<usize>,
+
+    /// Dedicated worker pool, e.g. seeds=2,project=12,platform=linux-avx2,timeout=3600,memory=2048,nice=10
+    #[clap(long, global = true, value_name = "NAME=SIZE[,KEY=VALUE...]")]
+    pool: Vec<PoolSettings>,
+
+    /// Override the priority of a platform's binaries (higher runs first), e.g. linux-avx2=10
+    #[clap(long, global = true, value_name = "PLATFORM=PRIORITY", parse(try_from_str = parse_key_value))]
+    platform_priority: Vec<(String, i32)>,
+
+    /// Only run these projects, by id or name (repeatable)
+    #[clap(long, global = true, value_name = "PROJECT")]
+    include_project: Vec<String>,
+
+    /// Never run these projects, by id or name (repeatable)
+    #[clap(long, global = true, value_name = "PROJECT")]
+    exclude_project: Vec<String>,
+
+    /// Relative share of compute for a project, e.g. 12=2.5 (default 1)
+    #[clap(long, global = true, value_name = "PROJECT=WEIGHT", parse(try_from_str = parse_key_value))]
+    project_weight: Vec<(String, f64)>,
+
+    /// Maximum concurrent tasks for a project, e.g. 12=4
+    #[clap(long, global = true, value_name = "PROJECT=COUNT", parse(try_from_str = parse_key_value))]
+    project_max_tasks: Vec<(String, usize)>,
+
+    /// Cores each task of a project uses, overriding the project's own value, e.g. 12=4
+    #[clap(long, global = true, value_name = "PROJECT=CORES", parse(try_from_str = parse_key_value))]
+    project_cores: Vec<(String, usize)>,
+
+    /// Seconds between refreshes of the platform and project lists
+    #[clap(long, global = true)]
+    refresh_interval: Option<u64>,
+
+    /// Serve Prometheus metrics on this address, e.g. 0.0.0.0:9100
+    #[clap(long, global = true)]
+    metrics_address: Option<String>,
+
+    /// Serve the health checks on this address at /healthz and /readyz, e.g. 0.0.0.0:9101
+    #[clap(long, global = true)]
+    health_address: Option<String>,
+
+    /// Most verbose level of messages to log: off, error, warn, info, debug or trace
+    #[clap(long, global = true)]
+    log_level: Option<String>,
 
-    /// Worker count
-    #[clap(short, long, default_value_t = 0)]
-    workers: usize,
+    /// Format of the logs: human, or json for one JSON object per line
+    #[clap(long, global = true)]
+    log_format: Option<LogFormat>,
+
+    /// Also write the logs to rotated files in the logs directory of the data directory
+    #[clap(long, global = true)]
+    log_file: bool,
+
+    /// Export tracing spans to this OTLP/HTTP collector, e.g. http://127.0.0.1:4318
+    #[clap(long, global = true)]
+    otlp_endpoint: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Set up logging
-    TermLogger::init(
-        log::LevelFilter::Info,
-        Config::default(),
-        TerminalMode::Mixed,
-        ColorChoice::Auto,
-    )?;
-
-    // Parse command line arguments
-    let mut opts = Opts::parse();
-
-    if opts.workers == 0 {
-        opts.workers = num_cpus::get() / 2;
-    }
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Run the client, the default when no command is given
+    Run(RunOpts),
+    /// Detect the platforms supported by this host
+    Detect,
+    /// List the projects compatible with this host and their binaries
+    Projects,
+    /// Download the binaries of all allowed projects ahead of time
+    Fetch,
+    /// Run a project binary on a local input file, without requesting or submitting assignments
+    Exec {
+        /// Project id or name
+        project: String,
+
+        /// Input file passed to the binary with --input
+        input: PathBuf,
+
+        /// Only try the binaries of these platforms, by id or name (repeatable)
+        #[clap(long, value_name = "PLATFORM")]
+        platform: Vec<String>,
+
+        /// Threads the binary may use [default: the project's core count]
+        #[clap(long)]
+        threads: Option<usize>,
+
+        /// Kill the binary after this many seconds
+        #[clap(long)]
+        timeout: Option<u64>,
+    },
+    /// Show what the running client is doing
+    Status {
+        /// Print the status as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Have the running client stop asking for tasks, letting running ones finish
+    Pause,
+    /// Have the paused client ask for tasks again
+    Resume,
+    /// Have the running client finish its running tasks, then exit
+    Drain,
+    /// Resize the default pool of the running client, 0 for half the CPUs not used by other pools
+    SetWorkers {
+        workers: usize,
+    },
+    /// Check the health of the running client, exiting with 1 if it's unhealthy
+    Healthcheck {
+        /// Check that the client is ready to take tasks instead, which also requires the server to be reachable
+        #[clap(long)]
+        ready: bool,
+
+        /// Print the result of the checks as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// List the assignments this host worked on, the most recent last
+    History(HistoryOpts),
+    /// Show the throughput and reliability of each project on this host, from the history
+    Stats {
+        /// Only assignments of this project, by id or name
+        #[clap(long)]
+        project: Option<String>,
 
-    info!("");
-    info!("<bold><blue>DICC Client</>");
-    info!("<bold><blue>Version: 0.1.0</>");
-    info!("<bold><blue>Using {} workers</>", opts.workers);
-    info!("");
+        /// Only assignments finished since this time, e.g. 7d or 2022-06-01T00:00:00Z
+        #[clap(long, parse(try_from_str = command::history::parse_time))]
+        since: Option<SystemTime>,
 
-    // Fetch platforms
-    info!("<green><bold>Fetching platforms...</>");
-    let api = MCAtHomeAPI::new(opts.api_key.as_str());
-    let platforms = api.list_platforms().await?;
+        /// Only assignments finished before this time, e.g. 1d or 2022-06-02T00:00:00Z
+        #[clap(long, parse(try_from_str = command::history::parse_time))]
+        until: Option<SystemTime>,
 
-    let mut manager = manager::platform::PlatformManager::new();
-    for platform in platforms {
-        manager.add(platform);
+        /// Break the statistics down by the platform of the binary
+        #[clap(long)]
+        by_platform: bool,
+
+        /// Print the statistics as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Inspect or clean the cached detectors, binaries and inputs
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Run(RunOpts::default())
     }
+}
 
-    let mut ts = Instant::now();
-    info!("<green><bold>Detecting platforms...</>");
+#[derive(Args, Debug, Clone, Default)]
+struct RunOpts {
+    /// Exit after processing this many tasks
+    #[clap(long)]
+    max_tasks: Option<usize>,
+
+    /// Stop taking new tasks after running for this long, e.g. 90m or 2h30m
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    max_runtime: Option<Duration>,
+
+    /// Exit once the server has no task left for this host
+    #[clap(long)]
+    exit_when_idle: bool,
+
+    /// Show a live dashboard of the workers instead of the log, q goes back to the log
+    #[clap(long)]
+    dashboard: bool,
+}
 
-    let valid_platforms = manager.detect().await;
-    info!("<green><bold>Detected in {}ms. Found {} platform(s).</>", ts.elapsed().as_millis(), valid_platforms.len());
+#[derive(Args, Debug, Clone)]
+struct HistoryOpts {
+    /// Only assignments of this project, by id or name
+    #[clap(long)]
+    project: Option<String>,
 
-    // Find projects
-    ts = Instant::now();
-    info!("<green><bold>Fetching projects...</>");
-    let projects = api.get_projects_for_platforms(&valid_platforms).await?;
-    info!("<green><bold>Found {} project(s) in {}ms.</>", projects.len(), ts.elapsed().as_millis());
+    /// Only assignments that ended this way: succeeded, failed or timed_out
+    #[clap(long)]
+    outcome: Option<Outcome>,
 
-    for project in &projects {
-        info!("<bold>{} - {}</>", project.id, project.name);
-        for (_, platform) in &project.platforms {
-            info!(" - <bright-black>{}</>", platform.platform.name);
+    /// Only assignments finished since this time, e.g. 24h or 2022-06-01T00:00:00Z
+    #[clap(long, parse(try_from_str = command::history::parse_time))]
+    since: Option<SystemTime>,
+
+    /// Only assignments finished before this time, e.g. 1h or 2022-06-02T00:00:00Z
+    #[clap(long, parse(try_from_str = command::history::parse_time))]
+    until: Option<SystemTime>,
+
+    /// Show at most this many assignments, the most recent ones [default: 50 for the table]
+    #[clap(long)]
+    limit: Option<usize>,
+
+    /// Output format: table, csv or json
+    #[clap(long, default_value = "table")]
+    format: HistoryFormat,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum CacheCommand {
+    /// Print the disk space used by the cache
+    Show,
+    /// Remove leftover inputs, and with --all every cached detector and binary
+    Clean {
+        /// Also remove the detectors and binaries, they are downloaded again when needed
+        #[clap(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigCommand {
+    /// Print the effective configuration, with secrets redacted
+    Show,
+}
+
+impl Opts {
+    /// Applies the command line flags on top of the loaded config.
+    fn apply(&self, config: &mut Config) {
+        if let Some(api_key) = &self.api_key {
+            config.api_key = Some(Secret::new(api_key));
+        }
+        if let Some(api_key_file) = &self.api_key_file {
+            config.api_key = None;
+            config.api_key_file = Some(api_key_file.clone());
+        }
+        if let Some(base_url) = &self.base_url {
+            config.base_url = base_url.clone();
+        }
+        if let Some(data_dir) = &self.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if let Some(refresh_interval) = self.refresh_interval {
+            config.refresh_interval = refresh_interval;
+        }
+        if let Some(metrics_address) = &self.metrics_address {
+            config.metrics_address = Some(metrics_address.clone());
+        }
+        if let Some(health_address) = &self.health_address {
+            config.health_address = Some(health_address.clone());
+        }
+        if let Some(log_level) = &self.log_level {
+            config.log_level = log_level.clone();
         }
+        if let Some(log_format) = self.log_format {
+            config.log_format = log_format;
+        }
+        if self.log_file {
+            config.log_file.enabled = true;
+        }
+        if let Some(otlp_endpoint) = &self.otlp_endpoint {
+            config.tracing.otlp_endpoint = Some(otlp_endpoint.clone());
+        }
+
+        config.pools.extend(self.pool.iter().cloned());
+        config.platforms.priority.extend(self.platform_priority.iter().cloned());
+        config.projects.include.extend(self.include_project.iter().cloned());
+        config.projects.exclude.extend(self.exclude_project.iter().cloned());
+        config.projects.weights.extend(self.project_weight.iter().cloned());
+        config.projects.max_tasks.extend(self.project_max_tasks.iter().cloned());
+        config.projects.cores.extend(self.project_cores.iter().cloned());
     }
+}
 
-    info!("<green><bold>Creating threads...</>");
-    let platform_ids = valid_platforms.keys().cloned().collect::<Vec<i64>>();
+/// Loads the config files and environment variables, then applies the command line flags.
+fn load_config(opts: &Opts) -> Result<(Config, Vec<PathBuf>), Box<dyn std::error::Error>> {
+    let (mut config, sources) = Config::load(opts.config.as_deref())?;
+    opts.apply(&mut config);
+    config.validate()?;
+    Ok((config, sources))
+}
 
-    for i in 0..opts.workers {
-        let worker = WorkerThread::new(i as i32, &api, &projects, &platform_ids);
-        thread::spawn(move || {
-            let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
-            runtime.block_on(worker.run());
-        });
-    }
+fn parse_key_value<T>(s: &str) -> Result<(String, T), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))?;
+    let value = value
+        .parse::<T>()
+        .map_err(|e| format!("invalid value `{}`: {}", value, e))?;
+    Ok((key.to_string(), value))
+}
 
-    loop {
-        thread::sleep(Duration::from_secs(1));
+#[tokio::main]
+async fn main() {
+    // Set up logging, the level and format are set once the config is loaded
+    logging::init().expect("Unable to set up logging");
+
+    match run(Opts::parse()).await {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            error!("<red>{}</>", err);
+            std::process::exit(1);
+        }
     }
 }
+
+/// Runs the command, returning the exit code.
+async fn run(opts: Opts) -> Result<i32, Box<dyn std::error::Error>> {
+    let (config, sources) = load_config(&opts)?;
+    log::set_max_level(config.get_log_level()?);
+    logging::set_format(config.log_format);
+
+    let result = match opts.command.clone().unwrap_or_default() {
+        Command::Run(run) => {
+            let paths = Config::get_paths(opts.config.as_deref())
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            let load = Box::new(move || load_config(&opts).map(|(config, _)| config));
+            let limits = BatchLimits {
+                max_tasks: run.max_tasks,
+                max_runtime: run.max_runtime,
+                exit_when_idle: run.exit_when_idle,
+            };
+            return command::run::run(config, load, paths, limits, run.dashboard).await;
+        }
+        Command::Detect => command::detect::detect(&config).await,
+        Command::Projects => command::projects::list(&config).await,
+        Command::Fetch => command::fetch::fetch(&config).await,
+        Command::Exec {
+            project,
+            input,
+            platform,
+            threads,
+            timeout,
+        } => command::exec::exec(&config, &project, &input, &platform, threads, timeout).await,
+        Command::Status { json } => command::control::status(&config, json).await,
+        Command::Pause => command::control::pause(&config).await,
+        Command::Resume => command::control::resume(&config).await,
+        Command::Drain => command::control::drain(&config).await,
+        Command::SetWorkers { workers } => command::control::set_workers(&config, workers).await,
+        Command::Healthcheck { ready, json } => return command::control::healthcheck(&config, ready, json).await,
+        Command::History(history) => {
+            let filter = HistoryFilter {
+                project: history.project,
+                outcome: history.outcome,
+                since: history.since,
+                until: history.until,
+                limit: history.limit,
+            };
+            command::history::history(&config, filter, history.format)
+        }
+        Command::Stats {
+            project,
+            since,
+            until,
+            by_platform,
+            json,
+        } => {
+            let filter = HistoryFilter {
+                project,
+                since,
+                until,
+                ..HistoryFilter::default()
+            };
+            command::stats::show(&config, filter, by_platform, json)
+        }
+        Command::Cache { command: CacheCommand::Show } => command::cache::show(&config),
+        Command::Cache { command: CacheCommand::Clean { all } } => command::cache::clean(&config, all),
+        Command::Config { command: ConfigCommand::Show } => command::config::show(&config, &sources),
+    };
+    result.map(|_| 0)
+}
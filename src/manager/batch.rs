@@ -0,0 +1,309 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use simplelog::info;
+use tokio::sync::Notify;
+
+/// Limits on how much work the client takes on before exiting.
+#[derive(Debug, Clone, Default)]
+pub struct BatchLimits {
+    /// Stop after this many tasks.
+    pub max_tasks: Option<usize>,
+    /// Stop taking new tasks once the client has been running for this long.
+    pub max_runtime: Option<Duration>,
+    /// Stop as soon as the server has no task for a worker.
+    pub exit_when_idle: bool,
+}
+
+#[derive(Debug, Default)]
+struct BatchState {
+    /// Tasks being requested, running or finished.
+    started: usize,
+    succeeded: usize,
+    failed: usize,
+    /// Whether new tasks are refused.
+    stopped: bool,
+}
+
+impl BatchState {
+    fn running(&self) -> usize {
+        self.started - self.succeeded - self.failed
+    }
+}
+
+/// Counts the tasks processed by all workers and decides when the client stops taking work.
+///
+/// Without limits the batch never stops.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    limits: BatchLimits,
+    deadline: Option<Instant>,
+    state: Arc<Mutex<BatchState>>,
+    changed: Arc<Notify>,
+}
+
+/// Number of tasks finished by a batch.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl Batch {
+    pub fn new(limits: BatchLimits) -> Batch {
+        Batch {
+            deadline: limits.max_runtime.map(|runtime| Instant::now() + runtime),
+            limits,
+            state: Arc::new(Mutex::new(BatchState::default())),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Starts a task unless a limit was reached, in which case the batch stops.
+    ///
+    /// Dropping the returned task without finishing it gives its place back.
+    pub fn try_start(&self) -> Option<BatchTask> {
+        let mut state = self.state.lock().unwrap();
+        if self.deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            self.stop(&mut state, "the maximum runtime was reached");
+        }
+        if self.limits.max_tasks.map(|max| state.started >= max).unwrap_or(false) {
+            self.stop(&mut state, "the maximum number of tasks was reached");
+        }
+        if state.stopped {
+            return None;
+        }
+
+        state.started += 1;
+        Some(self.create_task())
+    }
+
+    /// Starts a task even if the batch stopped, for an assignment the server already handed out.
+    pub fn force_start(&self) -> BatchTask {
+        self.state.lock().unwrap().started += 1;
+        self.create_task()
+    }
+
+    /// Reports that the server had no task for a worker, returns whether the worker should stop.
+    pub fn idle(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if self.limits.exit_when_idle {
+            self.stop(&mut state, "there are no tasks left");
+        }
+        state.stopped
+    }
+
+    /// Stops taking new tasks, so the client exits once the running ones are done.
+    pub fn drain(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.stop(&mut state, "a drain was requested");
+    }
+
+    /// Returns the tasks finished so far.
+    pub fn get_summary(&self) -> BatchSummary {
+        let state = self.state.lock().unwrap();
+        BatchSummary {
+            succeeded: state.succeeded,
+            failed: state.failed,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.lock().unwrap().stopped
+    }
+
+    /// Waits until the batch stopped and every started task finished.
+    pub async fn wait(&self) -> BatchSummary {
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if self.deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                    self.stop(&mut state, "the maximum runtime was reached");
+                }
+                if state.stopped && state.running() == 0 {
+                    break;
+                }
+            }
+
+            match self.deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = changed => {}
+                        _ = tokio::time::sleep_until(deadline.into()) => {}
+                    }
+                }
+                None => changed.await,
+            }
+        }
+        self.get_summary()
+    }
+
+    fn stop(&self, state: &mut BatchState, reason: &str) {
+        if !state.stopped {
+            state.stopped = true;
+            info!(
+                "<yellow><bold>Not taking new tasks, {}. Waiting for {} running task(s)...</>",
+                reason,
+                state.running()
+            );
+            self.changed.notify_one();
+        }
+    }
+
+    fn create_task(&self) -> BatchTask {
+        BatchTask {
+            state: self.state.clone(),
+            changed: self.changed.clone(),
+            finished: false,
+        }
+    }
+}
+
+/// A task counted by the batch.
+pub struct BatchTask {
+    state: Arc<Mutex<BatchState>>,
+    changed: Arc<Notify>,
+    finished: bool,
+}
+
+impl BatchTask {
+    /// Records whether the task's result was computed and submitted.
+    pub fn finish(mut self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        if success {
+            state.succeeded += 1;
+        } else {
+            state.failed += 1;
+        }
+        self.finished = true;
+        self.changed.notify_one();
+    }
+}
+
+impl Drop for BatchTask {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.state.lock().unwrap().started -= 1;
+            self.changed.notify_one();
+        }
+    }
+}
+
+impl BatchSummary {
+    /// Exit code of the client: 0 if no task failed, 2 if some did and 3 if all of them did.
+    pub fn get_exit_code(&self) -> i32 {
+        match (self.succeeded, self.failed) {
+            (_, 0) => 0,
+            (0, _) => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn print(&self) {
+        info!(
+            "<bold><blue>Processed {} task(s): {} succeeded, {} failed.</>",
+            self.succeeded + self.failed,
+            self.succeeded,
+            self.failed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(succeeded: usize, failed: usize) -> BatchSummary {
+        BatchSummary { succeeded, failed }
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(summary(0, 0).get_exit_code(), 0);
+        assert_eq!(summary(2, 0).get_exit_code(), 0);
+        assert_eq!(summary(2, 1).get_exit_code(), 2);
+        assert_eq!(summary(0, 2).get_exit_code(), 3);
+    }
+
+    #[test]
+    fn max_tasks_stops_the_batch() {
+        let batch = Batch::new(BatchLimits {
+            max_tasks: Some(2),
+            ..BatchLimits::default()
+        });
+        let first = batch.try_start().unwrap();
+        // A task dropped without finishing gives its place back
+        drop(batch.try_start().unwrap());
+        let second = batch.try_start().unwrap();
+        assert!(batch.try_start().is_none());
+        assert!(batch.is_stopped());
+
+        first.finish(true);
+        second.finish(false);
+    }
+
+    #[test]
+    fn force_start_counts_assignments_of_a_stopped_batch() {
+        let batch = Batch::new(BatchLimits {
+            max_tasks: Some(1),
+            ..BatchLimits::default()
+        });
+        batch.try_start().unwrap().finish(true);
+        assert!(batch.try_start().is_none());
+
+        batch.force_start().finish(false);
+        let summary = batch.get_summary();
+        assert_eq!((summary.succeeded, summary.failed), (1, 1));
+    }
+
+    #[test]
+    fn idle_stops_only_with_exit_when_idle() {
+        let batch = Batch::new(BatchLimits::default());
+        assert!(!batch.idle());
+        assert!(batch.try_start().is_some());
+
+        let batch = Batch::new(BatchLimits {
+            exit_when_idle: true,
+            ..BatchLimits::default()
+        });
+        assert!(batch.idle());
+        assert!(batch.try_start().is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_summarizes_the_finished_tasks() {
+        let batch = Batch::new(BatchLimits {
+            max_tasks: Some(3),
+            ..BatchLimits::default()
+        });
+        let tasks = (0..3).map(|_| batch.try_start().unwrap()).collect::<Vec<BatchTask>>();
+        assert!(batch.try_start().is_none());
+
+        let waiting = tokio::spawn({
+            let batch = batch.clone();
+            async move { batch.wait().await }
+        });
+        for (i, task) in tasks.into_iter().enumerate() {
+            task.finish(i != 1);
+        }
+
+        let summary = waiting.await.unwrap();
+        assert_eq!((summary.succeeded, summary.failed), (2, 1));
+        assert_eq!(summary.get_exit_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn wait_reports_every_task_failed() {
+        let batch = Batch::new(BatchLimits {
+            max_tasks: Some(1),
+            ..BatchLimits::default()
+        });
+        let task = batch.try_start().unwrap();
+        assert!(batch.try_start().is_none());
+        task.finish(false);
+
+        let summary = batch.wait().await;
+        assert_eq!(summary.get_exit_code(), 3);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use simplelog::{error, info, warn};
+use tokio::sync::{watch, Notify};
+
+use crate::config::ConfigHandle;
+use crate::data::project::Project;
+use crate::hooks::{Event, Hooks};
+use crate::manager::platform::{Platform, PlatformManager};
+use crate::MCAtHomeAPI;
+
+/// Minimum time between two refreshes triggered by unknown projects.
+const MIN_REFRESH_GAP: Duration = Duration::from_secs(60);
+
+/// A snapshot of the platforms detected on this host and the projects compatible with them.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    pub platforms: HashMap<i64, Platform>,
+    pub platform_ids: Vec<i64>,
+    pub projects: Vec<Project>,
+    pub priorities: HashMap<i64, i32>,
+}
+
+impl Catalog {
+    /// Fetches and detects platforms, then fetches the projects compatible with them.
+    ///
+    /// `overrides` are local priority overrides keyed by platform id or name.
+    pub async fn fetch(
+        api: &MCAtHomeAPI,
+        data_dir: &Path,
+        overrides: &[(String, i32)],
+    ) -> Result<Catalog, Box<dyn std::error::Error>> {
+        let mut manager = PlatformManager::new();
+        for platform in api.list_platforms().await? {
+            manager.add(platform);
+        }
+
+        let mut ts = Instant::now();
+        let platforms = manager.detect(data_dir).await?;
+        info!("<green><bold>Detected in {}ms. Found {} platform(s).</>", ts.elapsed().as_millis(), platforms.len());
+
+        ts = Instant::now();
+        let projects = api.get_projects_for_platforms(&platforms).await?;
+        info!("<green><bold>Found {} project(s) in {}ms.</>", projects.len(), ts.elapsed().as_millis());
+
+        // Resolve local priority overrides, by platform id or name
+        let mut priorities: HashMap<i64, i32> = HashMap::new();
+        for (key, priority) in overrides {
+            match platforms
+                .values()
+                .find(|p| p.name == *key || p.id.to_string() == *key)
+            {
+                Some(platform) => {
+                    priorities.insert(platform.id, *priority);
+                }
+                None => warn!("<yellow>Ignoring priority for unknown platform {}</>", key),
+            }
+        }
+
+        let mut platform_ids = platforms.keys().cloned().collect::<Vec<i64>>();
+        platform_ids.sort_unstable();
+
+        Ok(Catalog {
+            platforms,
+            platform_ids,
+            projects,
+            priorities,
+        })
+    }
+
+    pub fn get_project(&self, id: i64) -> Option<&Project> {
+        self.projects.iter().find(|p| p.id == id)
+    }
+
+    /// Logs the platforms and projects along with their binaries, in order of preference.
+    pub fn print(&self) {
+        for project in &self.projects {
+            if project.cores > 1 {
+                info!("<bold>{} - {}</> ({} cores)", project.id, project.name, project.cores);
+            } else {
+                info!("<bold>{} - {}</>", project.id, project.name);
+            }
+            for platform in project.get_platforms(&self.platform_ids, &self.priorities) {
+                info!(
+                    " - <bright-black>{} (priority {})</>",
+                    platform.platform.name,
+                    platform.get_priority(&self.priorities)
+                );
+            }
+        }
+    }
+
+    /// Logs the platforms and projects that were added, removed or changed compared to `old`.
+    pub fn print_changes(&self, old: &Catalog) {
+        for platform in self.platforms.values() {
+            if !old.platforms.contains_key(&platform.id) {
+                info!("<green>Platform added: {}</>", platform.name);
+            }
+        }
+        for platform in old.platforms.values() {
+            if !self.platforms.contains_key(&platform.id) {
+                info!("<red>Platform removed: {}</>", platform.name);
+            }
+        }
+
+        for project in &self.projects {
+            match old.get_project(project.id) {
+                None => info!("<green>Project added: {} - {}</>", project.id, project.name),
+                Some(previous) if previous != project => {
+                    info!("<yellow>Project changed: {} - {}</>", project.id, project.name)
+                }
+                _ => {}
+            }
+        }
+        for project in &old.projects {
+            if self.get_project(project.id).is_none() {
+                info!("<red>Project removed: {} - {}</>", project.id, project.name);
+            }
+        }
+    }
+
+    /// Returns the hook events for the platforms and binaries that changed compared to `old`.
+    pub fn get_events(&self, old: &Catalog) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut added = self
+            .platforms
+            .values()
+            .filter(|p| !old.platforms.contains_key(&p.id))
+            .map(|p| p.name.clone())
+            .collect::<Vec<String>>();
+        let mut removed = old
+            .platforms
+            .values()
+            .filter(|p| !self.platforms.contains_key(&p.id))
+            .map(|p| p.name.clone())
+            .collect::<Vec<String>>();
+        if !added.is_empty() || !removed.is_empty() {
+            added.sort();
+            removed.sort();
+            events.push(Event::PlatformsChanged { added, removed });
+        }
+
+        // Only binaries replaced on platforms both catalogs have, new projects aren't updates
+        for project in &self.projects {
+            let previous = match old.get_project(project.id) {
+                Some(previous) => previous,
+                None => continue,
+            };
+            let mut platforms = project.platforms.values().collect::<Vec<_>>();
+            platforms.sort_by_key(|p| p.platform.id);
+            for platform in platforms {
+                match previous.platforms.get(&platform.platform.id) {
+                    Some(old) if old.binary != platform.binary => events.push(Event::BinaryUpdated {
+                        project_id: project.id,
+                        project: project.name.clone(),
+                        platform: platform.platform.name.clone(),
+                        checksum: platform.binary.get_checksum(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        events
+    }
+}
+
+/// A handle to the current catalog, shared by the worker threads.
+#[derive(Clone)]
+pub struct CatalogHandle {
+    receiver: watch::Receiver<Arc<Catalog>>,
+    refresh: Arc<Notify>,
+}
+
+impl CatalogHandle {
+    pub fn get(&self) -> Arc<Catalog> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Asks the refresher for a new catalog without waiting for it.
+    pub fn request_refresh(&self) {
+        self.refresh.notify_one();
+    }
+
+    /// Asks the refresher for a new catalog and waits up to `timeout` for it.
+    pub async fn refresh(&self, timeout: Duration) -> Arc<Catalog> {
+        let mut receiver = self.receiver.clone();
+        receiver.borrow_and_update();
+        self.refresh.notify_one();
+
+        let _ = tokio::time::timeout(timeout, receiver.changed()).await;
+        self.get()
+    }
+}
+
+/// Periodically refreshes the catalog, or sooner when a worker asks for it.
+pub struct CatalogRefresher {
+    api: MCAtHomeAPI,
+    config: ConfigHandle,
+    sender: watch::Sender<Arc<Catalog>>,
+    refresh: Arc<Notify>,
+    hooks: Hooks,
+}
+
+impl CatalogRefresher {
+    pub fn new(
+        api: &MCAtHomeAPI,
+        catalog: Catalog,
+        config: &ConfigHandle,
+        hooks: &Hooks,
+    ) -> (CatalogRefresher, CatalogHandle) {
+        let (sender, receiver) = watch::channel(Arc::new(catalog));
+        let refresh = Arc::new(Notify::new());
+
+        let refresher = CatalogRefresher {
+            api: api.clone(),
+            config: config.clone(),
+            sender,
+            refresh: refresh.clone(),
+            hooks: hooks.clone(),
+        };
+        (refresher, CatalogHandle { receiver, refresh })
+    }
+
+    pub async fn run(&self) {
+        let mut last_refresh = Instant::now();
+        loop {
+            let interval = Duration::from_secs(self.config.get().refresh_interval);
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.refresh.notified() => {
+                    let elapsed = last_refresh.elapsed();
+                    if elapsed < MIN_REFRESH_GAP {
+                        tokio::time::sleep(MIN_REFRESH_GAP - elapsed).await;
+                    }
+                }
+            }
+
+            info!("<green><bold>Refreshing platforms and projects...</>");
+            last_refresh = Instant::now();
+            let config = self.config.get();
+            match Catalog::fetch(&self.api, &config.data_dir, &config.get_priorities()).await {
+                Ok(catalog) => {
+                    catalog.print_changes(&self.sender.borrow());
+                    for event in catalog.get_events(&self.sender.borrow()) {
+                        self.hooks.fire(event);
+                    }
+                    self.sender.send_replace(Arc::new(catalog));
+                }
+                Err(err) => {
+                    error!("<red>Failed to refresh platforms and projects: {}</>", err);
+                }
+            }
+        }
+    }
+}
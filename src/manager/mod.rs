@@ -1,2 +1,10 @@
+pub mod batch;
+pub mod catalog;
+pub mod pause;
 pub mod platform;
-pub mod worker;
\ No newline at end of file
+pub mod pool;
+pub mod reload;
+pub mod scheduler;
+pub mod status;
+pub mod suspect;
+pub mod worker;
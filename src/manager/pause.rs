@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Pauses all workers before they ask for their next task. Running assignments are not affected.
+#[derive(Debug, Clone)]
+pub struct PauseSwitch {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl PauseSwitch {
+    pub fn new() -> PauseSwitch {
+        let (sender, receiver) = watch::channel(false);
+        PauseSwitch {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Pauses the workers, returns whether they were running.
+    pub fn pause(&self) -> bool {
+        !self.sender.send_replace(true)
+    }
+
+    /// Resumes the workers, returns whether they were paused.
+    pub fn resume(&self) -> bool {
+        self.sender.send_replace(false)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Waits until the workers are resumed.
+    pub async fn wait_resumed(&self) {
+        let mut receiver = self.receiver.clone();
+        while *receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for PauseSwitch {
+    fn default() -> PauseSwitch {
+        PauseSwitch::new()
+    }
+}
@@ -6,9 +6,8 @@ use tokio::{
 };
 
 use crate::data::download::Download;
-use crate::util::file::set_executable;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Platform {
     pub id: i64,
     pub name: String,
@@ -34,6 +33,7 @@ impl Platform {
     }
 }
 
+#[derive(Default)]
 pub struct PlatformManager {
     platforms: Vec<Platform>,
 }
@@ -49,12 +49,14 @@ impl PlatformManager {
         self.platforms.push(platform);
     }
 
-    pub async fn detect(&self) -> HashMap<i64, Platform> {
-        let dir = Path::new("platforms");
+    /// Downloads and runs the detector of every platform, returning the platforms supported by
+    /// this host.
+    pub async fn detect(&self, data_dir: &Path) -> Result<HashMap<i64, Platform>, Box<dyn std::error::Error>> {
+        let dir = data_dir.join("platforms");
         if !dir.exists() {
-            fs::create_dir_all(dir)
+            fs::create_dir_all(&dir)
                 .await
-                .expect("failed to create platforms directory");
+                .map_err(|e| format!("unable to create {}: {}", dir.display(), e))?;
         }
 
         let mut platforms: HashMap<i64, Platform> = HashMap::new();
@@ -63,18 +65,17 @@ impl PlatformManager {
 
             platform
                 .detector
-                .download_to_file(&path.as_path())
+                .download_to_file(path.as_path(), &|_, _| {})
                 .await
-                .expect("failed to download platform");
+                .map_err(|e| format!("unable to download the {} detector: {}", platform.name, e))?;
 
-            set_executable(&path).await;
-            if platform.detect(&path).await.unwrap() {
+            if platform.detect(&path).await? {
                 platforms.insert(platform.id, platform.to_owned());
                 info!("{}: {}", platform.name, "<green>OK</>");
             } else {
                 info!("{}: {}", platform.name, "<red>FAILED</>");
             }
         }
-        platforms
+        Ok(platforms)
     }
 }
\ No newline at end of file
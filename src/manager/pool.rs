@@ -0,0 +1,324 @@
+use std::any::Any;
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use simplelog::{error, info};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::manager::platform::Platform;
+use crate::manager::scheduler::ProjectFilter;
+use crate::manager::status::Activity;
+use crate::manager::worker::{WorkerContext, WorkerThread};
+
+/// Limits applied to every project binary run by a pool.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    /// Kill the binary if it runs for longer than this.
+    pub timeout: Option<Duration>,
+    /// Maximum address space of the binary, in bytes.
+    pub memory: Option<u64>,
+    /// Niceness of the binary. Unprivileged users can only increase it.
+    pub nice: Option<i32>,
+}
+
+/// A named set of worker threads sharing the same projects, platforms and limits.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub name: String,
+    pub size: usize,
+    pub projects: ProjectFilter,
+    /// Platforms whose binaries the pool may run, by id or name. Every platform if empty.
+    pub platforms: Vec<String>,
+    pub limits: ResourceLimits,
+}
+
+impl PoolConfig {
+    pub fn new(name: &str, size: usize) -> PoolConfig {
+        PoolConfig {
+            name: name.to_string(),
+            size,
+            projects: ProjectFilter::default(),
+            platforms: Vec::new(),
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    pub fn allows_platform(&self, platform: &Platform) -> bool {
+        self.platforms.is_empty()
+            || self
+                .platforms
+                .iter()
+                .any(|key| *key == platform.name || *key == platform.id.to_string())
+    }
+}
+
+/// A pool's config, and the cores its budget still has to take back after shrinking.
+struct PoolState {
+    config: Arc<PoolConfig>,
+    /// Cores removed from the budget while running tasks held them, taken back as they're released.
+    owed: usize,
+}
+
+/// A pool's worker threads, and the budget of cores they reserve from before running a task.
+#[derive(Clone)]
+pub struct WorkerPool {
+    state: Arc<Mutex<PoolState>>,
+    cores: Arc<Semaphore>,
+    resized: Arc<Notify>,
+    /// Slots of the worker threads currently running.
+    workers: Arc<Mutex<HashSet<usize>>>,
+}
+
+impl WorkerPool {
+    pub fn new(config: PoolConfig) -> WorkerPool {
+        WorkerPool {
+            cores: Arc::new(Semaphore::new(config.size)),
+            resized: Arc::new(Notify::new()),
+            state: Arc::new(Mutex::new(PoolState {
+                config: Arc::new(config),
+                owed: 0,
+            })),
+            workers: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn get_config(&self) -> Arc<PoolConfig> {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    /// Reserves `cores` cores from the pool's budget, waiting for running tasks to release them.
+    ///
+    /// Tasks needing more cores than the pool has are given the whole pool. Returns None once the
+    /// pool has no cores left.
+    pub async fn reserve_cores(&self, cores: usize) -> Option<CoreReservation> {
+        loop {
+            let resized = self.resized.notified();
+            let size = self.get_config().size;
+            if size == 0 {
+                return None;
+            }
+
+            let cores = cores.clamp(1, size);
+            tokio::select! {
+                permit = self.cores.clone().acquire_many_owned(cores as u32) => {
+                    return Some(CoreReservation {
+                        state: self.state.clone(),
+                        budget: self.cores.clone(),
+                        permit: Some(permit.expect("core budget closed")),
+                        cores,
+                    });
+                }
+                // The pool may have shrunk below the cores being waited for
+                _ = resized => {}
+            }
+        }
+    }
+
+    /// Returns true and releases the slot if the pool shrank below it, so its worker should stop.
+    pub fn try_retire(&self, slot: usize) -> bool {
+        let mut workers = self.workers.lock().unwrap();
+        if slot < self.get_config().size {
+            return false;
+        }
+        workers.remove(&slot);
+        true
+    }
+
+    /// Replaces the pool's config and resizes its core budget.
+    ///
+    /// Cores held by running tasks are taken out of the budget when they're released.
+    fn update(&self, config: PoolConfig) {
+        let mut state = self.state.lock().unwrap();
+        let old = state.config.size;
+        if config.size > old {
+            // Growing first cancels what a previous shrink still owes
+            let added = config.size - old;
+            let cancelled = added.min(state.owed);
+            state.owed -= cancelled;
+            self.cores.add_permits(added - cancelled);
+        } else {
+            for _ in config.size..old {
+                match self.cores.try_acquire() {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => state.owed += 1,
+                }
+            }
+        }
+        state.config = Arc::new(config);
+        self.resized.notify_waiters();
+    }
+
+    /// Returns the slots that have no running worker thread, marking them as running.
+    fn claim_free_slots(&self) -> Vec<usize> {
+        let mut workers = self.workers.lock().unwrap();
+        (0..self.get_config().size)
+            .filter(|slot| workers.insert(*slot))
+            .collect()
+    }
+}
+
+/// Cores reserved from a pool's budget, released when dropped.
+pub struct CoreReservation {
+    state: Arc<Mutex<PoolState>>,
+    budget: Arc<Semaphore>,
+    permit: Option<OwnedSemaphorePermit>,
+    cores: usize,
+}
+
+impl CoreReservation {
+    pub fn get_cores(&self) -> usize {
+        self.cores
+    }
+}
+
+impl Drop for CoreReservation {
+    fn drop(&mut self) {
+        // Keep the cores a shrink is still owed, and give the rest back
+        let mut state = self.state.lock().unwrap();
+        let kept = self.cores.min(state.owed);
+        state.owed -= kept;
+        if let Some(permit) = self.permit.take() {
+            permit.forget();
+        }
+        self.budget.add_permits(self.cores - kept);
+    }
+}
+
+/// Starts the worker pools and keeps them in line with the config.
+pub struct PoolManager {
+    context: WorkerContext,
+    pools: Vec<WorkerPool>,
+    next_id: i32,
+}
+
+impl PoolManager {
+    pub fn new(context: &WorkerContext) -> PoolManager {
+        PoolManager {
+            context: context.clone(),
+            pools: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Starts, resizes and reconfigures the pools to match `configs`.
+    ///
+    /// Workers of shrunk or removed pools stop once their current assignment is done.
+    pub fn apply(&mut self, configs: Vec<PoolConfig>) {
+        let names = configs.iter().map(|c| c.name.clone()).collect::<Vec<String>>();
+        for pool in &self.pools {
+            let config = pool.get_config();
+            if !names.contains(&config.name) && config.size > 0 {
+                info!("<yellow><bold>Stopping pool {}...</>", config.name);
+                let mut config = (*config).clone();
+                config.size = 0;
+                pool.update(config);
+            }
+        }
+
+        for config in configs {
+            let pool = match self.pools.iter().find(|p| p.get_config().name == config.name) {
+                Some(pool) => {
+                    let old = pool.get_config();
+                    if old.size != config.size {
+                        info!(
+                            "<green><bold>Resizing pool {} from {} to {} worker(s)...</>",
+                            config.name, old.size, config.size
+                        );
+                    }
+                    pool.update(config);
+                    pool.clone()
+                }
+                None => {
+                    info!(
+                        "<green><bold>Starting pool {} with {} worker(s)...</>",
+                        config.name, config.size
+                    );
+                    let pool = WorkerPool::new(config);
+                    self.pools.push(pool.clone());
+                    pool
+                }
+            };
+
+            for slot in pool.claim_free_slots() {
+                let worker = WorkerThread::new(self.next_id, slot, &pool, &self.context);
+                self.next_id += 1;
+                thread::spawn(move || {
+                    let status = worker.status.clone();
+                    let id = worker.id;
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let runtime = tokio::runtime::Runtime::new().expect("Unable to create a runtime");
+                        runtime.block_on(worker.run());
+                    }));
+                    // Keep the dead worker on the board, so the health checks report it
+                    if let Err(panic) = result {
+                        let error = get_panic_message(&*panic);
+                        error!("<red><bold>Worker thread #{} died: {}</>", id, error);
+                        status.set(Activity::Dead { error });
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Returns the message a thread panicked with.
+fn get_panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resize(pool: &WorkerPool, size: usize) {
+        pool.update(PoolConfig::new("default", size));
+    }
+
+    #[tokio::test]
+    async fn shrinking_takes_cores_back_when_released() {
+        let pool = WorkerPool::new(PoolConfig::new("default", 3));
+        let running = pool.reserve_cores(2).await.unwrap();
+        resize(&pool, 0);
+        assert_eq!(pool.cores.available_permits(), 0);
+
+        drop(running);
+        assert_eq!(pool.cores.available_permits(), 0);
+        resize(&pool, 2);
+        assert_eq!(pool.cores.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn growing_cancels_a_pending_shrink() {
+        let pool = WorkerPool::new(PoolConfig::new("default", 2));
+        let running = pool.reserve_cores(2).await.unwrap();
+        resize(&pool, 1);
+        resize(&pool, 3);
+        assert_eq!(pool.cores.available_permits(), 1);
+
+        drop(running);
+        assert_eq!(pool.cores.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn reserve_cores_gives_up_when_the_pool_is_emptied() {
+        let pool = WorkerPool::new(PoolConfig::new("default", 2));
+        let running = pool.reserve_cores(2).await.unwrap();
+        let waiting = pool.clone();
+        let waiter = tokio::spawn(async move { waiting.reserve_cores(1).await.is_none() });
+        tokio::task::yield_now().await;
+
+        resize(&pool, 0);
+        assert!(waiter.await.unwrap());
+        drop(running);
+        assert!(pool.reserve_cores(1).await.is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use simplelog::{error, info, warn};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::config::Config;
+use crate::logging;
+use crate::manager::catalog::CatalogHandle;
+use crate::manager::pool::PoolManager;
+use crate::manager::scheduler::Scheduler;
+
+/// How often the config files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config, Box<dyn std::error::Error>>>;
+
+/// A change made to the running client, kept across config reloads until it exits.
+#[derive(Debug, Clone)]
+pub enum ConfigChange {
+    SetWorkers(usize),
+    EnableProject(String),
+    DisableProject(String),
+}
+
+/// A change along with where to report whether it was applied.
+pub type ConfigChangeRequest = (ConfigChange, oneshot::Sender<Result<(), String>>);
+
+/// Reloads the config on SIGHUP or when a config file changes, and applies it to the running
+/// client without interrupting the assignments being executed.
+pub struct ConfigReloader {
+    load: ConfigLoader,
+    paths: Vec<PathBuf>,
+    sender: watch::Sender<Arc<Config>>,
+    pools: PoolManager,
+    scheduler: Scheduler,
+    catalog: CatalogHandle,
+    requests: mpsc::UnboundedReceiver<ConfigChangeRequest>,
+    changes: Vec<ConfigChange>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        load: ConfigLoader,
+        paths: Vec<PathBuf>,
+        sender: watch::Sender<Arc<Config>>,
+        pools: PoolManager,
+        scheduler: &Scheduler,
+        catalog: &CatalogHandle,
+        requests: mpsc::UnboundedReceiver<ConfigChangeRequest>,
+    ) -> ConfigReloader {
+        ConfigReloader {
+            load,
+            paths,
+            sender,
+            pools,
+            scheduler: scheduler.clone(),
+            catalog: catalog.clone(),
+            requests,
+            changes: Vec::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut hangup = Hangup::new();
+        let mut modified = self.get_modified();
+
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => info!("<green><bold>Received SIGHUP, reloading config...</>"),
+                _ = tokio::time::sleep(WATCH_INTERVAL) => {
+                    let current = self.get_modified();
+                    if current == modified || !self.sender.borrow().watch_config {
+                        continue;
+                    }
+                    modified = current;
+                    info!("<green><bold>Config file changed, reloading config...</>");
+                }
+                Some((change, reply)) = self.requests.recv() => {
+                    let _ = reply.send(self.change(change));
+                    continue;
+                }
+            }
+            self.reload();
+        }
+    }
+
+    fn get_modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths
+            .iter()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn reload(&mut self) {
+        let config = match (self.load)() {
+            Ok(config) => config,
+            Err(err) => {
+                error!("<red>Keeping the current config: {}</>", err);
+                return;
+            }
+        };
+
+        let config = merge(&self.sender.borrow(), config, &self.changes);
+        self.apply(config);
+        info!("<green><bold>Config reloaded.</>");
+    }
+
+    /// Applies a change on top of the current config, and on top of every reloaded one after.
+    fn change(&mut self, change: ConfigChange) -> Result<(), String> {
+        let mut config = (**self.sender.borrow()).clone();
+        change.apply(&mut config);
+        config.validate().map_err(|e| e.to_string())?;
+
+        info!("<green><bold>Applying change: {}</>", change);
+        self.changes.push(change);
+        self.apply(config);
+        Ok(())
+    }
+
+    fn apply(&mut self, config: Config) {
+        let old = self.sender.borrow().clone();
+        if let Ok(level) = config.get_log_level() {
+            log::set_max_level(level);
+        }
+        logging::set_format(config.log_format);
+        self.scheduler.set_policy(config.get_policy());
+        self.pools.apply(config.get_pools());
+        if config.platforms.priority != old.platforms.priority {
+            self.catalog.request_refresh();
+        }
+
+        self.sender.send_replace(Arc::new(config));
+    }
+}
+
+/// Returns a reloaded config with the changes made to the running client on top, keeping the
+/// settings that only take effect after a restart from the current config.
+fn merge(old: &Config, mut config: Config, changes: &[ConfigChange]) -> Config {
+    for change in changes {
+        change.apply(&mut config);
+    }
+
+    if config.api_key != old.api_key
+        || config.api_key_file != old.api_key_file
+        || config.base_url != old.base_url
+        || config.data_dir != old.data_dir
+        || config.control != old.control
+        || config.metrics_address != old.metrics_address
+        || config.health_address != old.health_address
+        || config.log_file != old.log_file
+        || config.tracing != old.tracing
+    {
+        warn!(
+            "<yellow>Changes to the API key, base_url, data_dir, control, metrics_address, health_address, \
+             log_file and tracing take effect after a restart</>"
+        );
+        config.api_key = old.api_key.clone();
+        config.api_key_file = old.api_key_file.clone();
+        config.base_url = old.base_url.clone();
+        config.data_dir = old.data_dir.clone();
+        config.control = old.control.clone();
+        config.metrics_address = old.metrics_address.clone();
+        config.health_address = old.health_address.clone();
+        config.log_file = old.log_file.clone();
+        config.tracing = old.tracing.clone();
+    }
+
+    config
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigChange::SetWorkers(workers) => write!(f, "set workers to {}", workers),
+            ConfigChange::EnableProject(project) => write!(f, "enable project {}", project),
+            ConfigChange::DisableProject(project) => write!(f, "disable project {}", project),
+        }
+    }
+}
+
+impl ConfigChange {
+    fn apply(&self, config: &mut Config) {
+        let projects = &mut config.projects;
+        match self {
+            ConfigChange::SetWorkers(workers) => config.workers = *workers,
+            ConfigChange::EnableProject(project) => {
+                projects.exclude.retain(|p| p != project);
+                if !projects.include.is_empty() && !projects.include.contains(project) {
+                    projects.include.push(project.clone());
+                }
+            }
+            ConfigChange::DisableProject(project) => {
+                if !projects.exclude.contains(project) {
+                    projects.exclude.push(project.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Receives SIGHUP, or nothing on platforms without it.
+struct Hangup {
+    #[cfg(not(target_os = "windows"))]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Hangup {
+    fn new() -> Hangup {
+        Hangup {
+            #[cfg(not(target_os = "windows"))]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .expect("Unable to listen for SIGHUP"),
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(not(target_os = "windows"))]
+        self.signal.recv().await;
+
+        #[cfg(target_os = "windows")]
+        futures::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_persist_across_reloads() {
+        let mut current = Config::default();
+        current.projects.include = vec!["seeds".to_string()];
+        let changes = vec![
+            ConfigChange::SetWorkers(3),
+            ConfigChange::EnableProject("multi".to_string()),
+            ConfigChange::DisableProject("old".to_string()),
+        ];
+
+        let mut loaded = current.clone();
+        loaded.workers = 8;
+        loaded.projects.exclude = vec!["multi".to_string()];
+        let config = merge(&current, loaded, &changes);
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.projects.include, vec!["seeds", "multi"]);
+        assert_eq!(config.projects.exclude, vec!["old"]);
+
+        // The next reload still has them, without duplicates
+        let mut loaded = current.clone();
+        loaded.workers = 6;
+        loaded.projects.exclude = vec!["old".to_string()];
+        let config = merge(&config, loaded, &changes);
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.projects.include, vec!["seeds", "multi"]);
+        assert_eq!(config.projects.exclude, vec!["old"]);
+    }
+
+    #[test]
+    fn restart_only_settings_are_kept() {
+        let current = Config::default();
+        let mut loaded = current.clone();
+        loaded.base_url = "http://127.0.0.1:1".to_string();
+        loaded.data_dir = PathBuf::from("/elsewhere");
+        loaded.health_address = Some("127.0.0.1:9101".to_string());
+        loaded.idle_sleep = current.idle_sleep + 1;
+
+        let config = merge(&current, loaded, &[]);
+        assert_eq!(config.base_url, current.base_url);
+        assert_eq!(config.data_dir, current.data_dir);
+        assert_eq!(config.health_address, current.health_address);
+        // Everything else is reloaded
+        assert_eq!(config.idle_sleep, current.idle_sleep + 1);
+    }
+}
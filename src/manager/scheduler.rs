@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::data::project::Project;
+
+/// Which projects to run, matched by project id or name.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectFilter {
+    /// Projects to run. Every compatible project is run if empty.
+    pub include: Vec<String>,
+    /// Projects to never run, even if included.
+    pub exclude: Vec<String>,
+}
+
+/// Local preferences for which projects to run and how much, matched by project id or name.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectPolicy {
+    pub filter: ProjectFilter,
+    /// Relative share of compute for each project, 1.0 if not set.
+    pub weights: Vec<(String, f64)>,
+    /// Maximum number of concurrent tasks for each project.
+    pub max_tasks: Vec<(String, usize)>,
+    /// Number of cores each task of a project uses, overriding what the project declares.
+    pub cores: Vec<(String, usize)>,
+    /// Scale each project's weight by its efficiency on this host, see [`Scheduler::set_efficiency`].
+    pub prefer_efficient: bool,
+}
+
+fn matches(key: &str, project: &Project) -> bool {
+    key == project.name || key == project.id.to_string()
+}
+
+impl ProjectFilter {
+    pub fn is_allowed(&self, project: &Project) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|key| matches(key, project)))
+            && !self.exclude.iter().any(|key| matches(key, project))
+    }
+}
+
+impl ProjectPolicy {
+    pub fn get_weight(&self, project: &Project) -> f64 {
+        self.weights
+            .iter()
+            .rev()
+            .find(|(key, _)| matches(key, project))
+            .map(|(_, weight)| *weight)
+            .unwrap_or(1.0)
+    }
+
+    pub fn get_max_tasks(&self, project: &Project) -> Option<usize> {
+        self.max_tasks
+            .iter()
+            .rev()
+            .find(|(key, _)| matches(key, project))
+            .map(|(_, max)| *max)
+    }
+
+    pub fn get_cores(&self, project: &Project) -> usize {
+        self.cores
+            .iter()
+            .rev()
+            .find(|(key, _)| matches(key, project))
+            .map(|(_, cores)| *cores)
+            .unwrap_or(project.cores)
+            .max(1)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProjectUsage {
+    running: usize,
+    completed: u32,
+    seconds: f64,
+}
+
+impl ProjectUsage {
+    /// Compute used so far, counting running tasks as taking the average task time.
+    fn get_estimated_seconds(&self) -> f64 {
+        let average = if self.completed > 0 {
+            self.seconds / self.completed as f64
+        } else {
+            1.0
+        };
+        self.seconds + self.running as f64 * average
+    }
+}
+
+/// A snapshot of the tasks and compute of a project.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsageSnapshot {
+    /// Tasks reserved by workers, being fetched, run or submitted.
+    pub running: usize,
+    pub completed: u32,
+    /// Compute used by the completed tasks, in core-seconds.
+    pub seconds: f64,
+}
+
+/// Decides which projects to ask the feeder for, shared by all worker threads.
+///
+/// Projects are preferred by how little compute they've had relative to their weight, so
+/// over time each project's share of compute converges to its share of the total weight.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    policy: Arc<RwLock<ProjectPolicy>>,
+    usage: Arc<Mutex<HashMap<i64, ProjectUsage>>>,
+    efficiency: Arc<RwLock<HashMap<i64, f64>>>,
+}
+
+impl Scheduler {
+    pub fn new(policy: ProjectPolicy) -> Scheduler {
+        Scheduler {
+            policy: Arc::new(RwLock::new(policy)),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+            efficiency: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Replaces the policy, for tasks reserved from now on.
+    pub fn set_policy(&self, policy: ProjectPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// Replaces the efficiency of each project, between 0 and 1, by project id.
+    ///
+    /// With `prefer_efficient` in the policy, weights are multiplied by the efficiency so projects
+    /// that waste compute on this host get less of it. Projects without one keep their weight.
+    pub fn set_efficiency(&self, efficiency: HashMap<i64, f64>) {
+        *self.efficiency.write().unwrap() = efficiency;
+    }
+
+    /// Returns the allowed projects below their task limit, the one furthest behind its fair share first.
+    pub fn select<'a>(&self, projects: impl IntoIterator<Item = &'a Project>) -> Vec<&'a Project> {
+        let policy = self.policy.read().unwrap();
+        let usage = self.usage.lock().unwrap();
+        let efficiency = self.efficiency.read().unwrap();
+        let mut projects = projects
+            .into_iter()
+            .filter(|p| policy.filter.is_allowed(p))
+            .filter(|p| match policy.get_max_tasks(p) {
+                Some(max) => usage.get(&p.id).map(|u| u.running).unwrap_or(0) < max,
+                None => true,
+            })
+            .map(|p| {
+                let used = usage.get(&p.id).map(|u| u.get_estimated_seconds()).unwrap_or(0.0);
+                let mut weight = policy.get_weight(p);
+                if policy.prefer_efficient {
+                    weight *= efficiency.get(&p.id).copied().unwrap_or(1.0);
+                }
+                (used / weight, p)
+            })
+            .collect::<Vec<(f64, &Project)>>();
+
+        projects.sort_by(|(a, pa), (b, pb)| a.total_cmp(b).then(pa.id.cmp(&pb.id)));
+        projects.into_iter().map(|(_, p)| p).collect()
+    }
+
+    /// Returns the number of cores a task of the project needs.
+    pub fn get_cores(&self, project: &Project) -> usize {
+        self.policy.read().unwrap().get_cores(project)
+    }
+
+    /// Returns the usage of every project that reserved a task so far, by project id.
+    pub fn get_usage(&self) -> HashMap<i64, UsageSnapshot> {
+        self.usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, usage)| {
+                let snapshot = UsageSnapshot {
+                    running: usage.running,
+                    completed: usage.completed,
+                    seconds: usage.seconds,
+                };
+                (*id, snapshot)
+            })
+            .collect()
+    }
+
+    /// Reserves a task slot for the project, unless it's already running its maximum.
+    pub fn try_reserve(&self, project: &Project) -> Option<TaskSlot> {
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(project.id).or_default();
+        if let Some(max) = self.policy.read().unwrap().get_max_tasks(project) {
+            if entry.running >= max {
+                return None;
+            }
+        }
+
+        entry.running += 1;
+        Some(TaskSlot {
+            usage: self.usage.clone(),
+            project_id: project.id,
+        })
+    }
+}
+
+/// A running task of a project, released when dropped.
+pub struct TaskSlot {
+    usage: Arc<Mutex<HashMap<i64, ProjectUsage>>>,
+    project_id: i64,
+}
+
+impl TaskSlot {
+    pub fn get_project_id(&self) -> i64 {
+        self.project_id
+    }
+
+    /// Records the compute used by the task, in core-seconds, and releases the slot.
+    pub fn complete(self, elapsed: Duration) {
+        if let Some(entry) = self.usage.lock().unwrap().get_mut(&self.project_id) {
+            entry.completed += 1;
+            entry.seconds += elapsed.as_secs_f64();
+        }
+    }
+}
+
+impl Drop for TaskSlot {
+    fn drop(&mut self) {
+        if let Some(entry) = self.usage.lock().unwrap().get_mut(&self.project_id) {
+            entry.running = entry.running.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(projects: &[&Project]) -> Vec<i64> {
+        projects.iter().map(|p| p.id).collect()
+    }
+
+    #[test]
+    fn select_prefers_the_project_furthest_behind_its_share() {
+        let projects = [Project::new(1, "a"), Project::new(2, "b")];
+        let scheduler = Scheduler::new(ProjectPolicy {
+            weights: vec![("b".to_string(), 3.0)],
+            ..ProjectPolicy::default()
+        });
+
+        // Ties are broken by id
+        assert_eq!(ids(&scheduler.select(&projects)), vec![1, 2]);
+
+        // 10s for a with weight 1 is further ahead than 20s for b with weight 3
+        scheduler.try_reserve(&projects[0]).unwrap().complete(Duration::from_secs(10));
+        scheduler.try_reserve(&projects[1]).unwrap().complete(Duration::from_secs(20));
+        assert_eq!(ids(&scheduler.select(&projects)), vec![2, 1]);
+
+        // Until b has had more than three times the compute of a
+        scheduler.try_reserve(&projects[1]).unwrap().complete(Duration::from_secs(20));
+        assert_eq!(ids(&scheduler.select(&projects)), vec![1, 2]);
+    }
+
+    #[test]
+    fn select_skips_filtered_projects() {
+        let projects = [Project::new(1, "a"), Project::new(2, "b"), Project::new(3, "c")];
+        let scheduler = Scheduler::new(ProjectPolicy {
+            filter: ProjectFilter {
+                include: vec!["a".to_string(), "3".to_string()],
+                exclude: vec!["c".to_string()],
+            },
+            ..ProjectPolicy::default()
+        });
+
+        assert_eq!(ids(&scheduler.select(&projects)), vec![1]);
+    }
+
+    #[test]
+    fn try_reserve_respects_max_tasks() {
+        let projects = [Project::new(1, "a"), Project::new(2, "b")];
+        let scheduler = Scheduler::new(ProjectPolicy {
+            max_tasks: vec![("a".to_string(), 2)],
+            ..ProjectPolicy::default()
+        });
+
+        let first = scheduler.try_reserve(&projects[0]).unwrap();
+        let second = scheduler.try_reserve(&projects[0]).unwrap();
+        assert!(scheduler.try_reserve(&projects[0]).is_none());
+        assert_eq!(ids(&scheduler.select(&projects)), vec![2]);
+        assert_eq!(scheduler.get_usage()[&1].running, 2);
+
+        // Projects without a limit are unaffected
+        assert!(scheduler.try_reserve(&projects[1]).is_some());
+
+        // Completing or dropping a slot frees it
+        first.complete(Duration::from_secs(1));
+        assert_eq!(scheduler.get_usage()[&1].completed, 1);
+        let third = scheduler.try_reserve(&projects[0]).unwrap();
+        assert!(scheduler.try_reserve(&projects[0]).is_none());
+        drop(second);
+        drop(third);
+        assert_eq!(scheduler.get_usage()[&1].running, 0);
+    }
+
+    #[test]
+    fn zero_max_tasks_excludes_the_project() {
+        let projects = [Project::new(1, "a")];
+        let scheduler = Scheduler::new(ProjectPolicy {
+            max_tasks: vec![("1".to_string(), 0)],
+            ..ProjectPolicy::default()
+        });
+
+        assert!(scheduler.select(&projects).is_empty());
+        assert!(scheduler.try_reserve(&projects[0]).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// What a worker thread is doing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Activity {
+    /// Waiting for tasks, or before retrying after an error.
+    Idle,
+    /// Waiting for the client to be resumed.
+    Paused,
+    /// Asking the server for a task of any of the projects.
+    Fetching { projects: Vec<String> },
+    /// Downloading the binary for an assignment.
+    Downloading {
+        assignment_id: i64,
+        project_id: i64,
+        project: String,
+        platform: String,
+    },
+    /// Running a binary on an assignment.
+    Running {
+        assignment_id: i64,
+        project_id: i64,
+        project: String,
+        platform: String,
+    },
+    /// Submitting the result of an assignment.
+    Submitting {
+        assignment_id: i64,
+        project_id: i64,
+        project: String,
+    },
+    /// Stopped by a panic. The worker isn't restarted, and the client reports itself unhealthy.
+    Dead { error: String },
+}
+
+impl Activity {
+    /// Returns the name of the state, as serialized.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Activity::Idle => "idle",
+            Activity::Paused => "paused",
+            Activity::Fetching { .. } => "fetching",
+            Activity::Downloading { .. } => "downloading",
+            Activity::Running { .. } => "running",
+            Activity::Submitting { .. } => "submitting",
+            Activity::Dead { .. } => "dead",
+        }
+    }
+}
+
+/// Number of finished assignments kept for [`StatusBoard::get_recent`].
+const RECENT_RESULTS: usize = 50;
+
+/// A snapshot of a worker thread's activity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub id: i32,
+    pub pool: String,
+    #[serde(flatten)]
+    pub activity: Activity,
+    /// Seconds since the activity started.
+    pub elapsed: u64,
+    /// Assignments handed out with the current one, waiting for this worker.
+    pub queued: usize,
+    /// Progress of the binary being downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadProgress>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub downloaded: u64,
+    /// Size of the download, if the server sent it.
+    pub total: Option<u64>,
+}
+
+/// An assignment a worker is done with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskOutcome {
+    pub assignment_id: i64,
+    pub project_id: i64,
+    pub project: String,
+    /// Whether the result was computed and submitted.
+    pub success: bool,
+    /// Seconds from the start of the assignment to its submission.
+    pub elapsed: f64,
+    /// Unix time the assignment finished at.
+    pub finished: u64,
+}
+
+/// Assignments of a project finished since the client started.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProjectTotals {
+    pub project: String,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug)]
+struct WorkerEntry {
+    pool: String,
+    activity: Activity,
+    since: Instant,
+    queued: usize,
+    download: Option<DownloadProgress>,
+}
+
+#[derive(Debug, Default)]
+struct BoardState {
+    workers: BTreeMap<i32, WorkerEntry>,
+    recent: VecDeque<TaskOutcome>,
+    projects: BTreeMap<i64, ProjectTotals>,
+}
+
+/// The current activity of every worker thread, and the assignments they recently finished.
+#[derive(Debug, Clone)]
+pub struct StatusBoard {
+    state: Arc<Mutex<BoardState>>,
+    started: Instant,
+}
+
+impl Default for StatusBoard {
+    fn default() -> StatusBoard {
+        StatusBoard {
+            state: Arc::new(Mutex::new(BoardState::default())),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl StatusBoard {
+    pub fn new() -> StatusBoard {
+        StatusBoard::default()
+    }
+
+    /// Adds an idle worker to the board, returning the handle it reports its activity with.
+    pub fn register(&self, id: i32, pool: &str) -> WorkerStatusHandle {
+        self.state.lock().unwrap().workers.insert(
+            id,
+            WorkerEntry {
+                pool: pool.to_string(),
+                activity: Activity::Idle,
+                since: Instant::now(),
+                queued: 0,
+                download: None,
+            },
+        );
+        WorkerStatusHandle {
+            board: self.clone(),
+            id,
+        }
+    }
+
+    pub fn get_workers(&self) -> Vec<WorkerStatus> {
+        self.state
+            .lock()
+            .unwrap()
+            .workers
+            .iter()
+            .map(|(id, entry)| WorkerStatus {
+                id: *id,
+                pool: entry.pool.clone(),
+                activity: entry.activity.clone(),
+                elapsed: entry.since.elapsed().as_secs(),
+                queued: entry.queued,
+                download: entry.download,
+            })
+            .collect()
+    }
+
+    /// Returns the last finished assignments, the most recent first.
+    pub fn get_recent(&self) -> Vec<TaskOutcome> {
+        self.state.lock().unwrap().recent.iter().rev().cloned().collect()
+    }
+
+    /// Returns the finished assignments of each project, by project id.
+    pub fn get_projects(&self) -> BTreeMap<i64, ProjectTotals> {
+        self.state.lock().unwrap().projects.clone()
+    }
+
+    /// Returns how long the board has been running for.
+    pub fn get_uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Reports the activity of a single worker thread.
+#[derive(Debug, Clone)]
+pub struct WorkerStatusHandle {
+    board: StatusBoard,
+    id: i32,
+}
+
+impl WorkerStatusHandle {
+    pub fn set(&self, activity: Activity) {
+        if let Some(entry) = self.board.state.lock().unwrap().workers.get_mut(&self.id) {
+            if entry.activity != activity {
+                entry.activity = activity;
+                entry.since = Instant::now();
+                entry.download = None;
+            }
+        }
+    }
+
+    /// Reports the progress of the download the worker is waiting for.
+    pub fn set_download(&self, downloaded: u64, total: Option<u64>) {
+        if let Some(entry) = self.board.state.lock().unwrap().workers.get_mut(&self.id) {
+            entry.download = Some(DownloadProgress { downloaded, total });
+        }
+    }
+
+    /// Reports the number of assignments waiting for the worker after the current one.
+    pub fn set_queued(&self, queued: usize) {
+        if let Some(entry) = self.board.state.lock().unwrap().workers.get_mut(&self.id) {
+            entry.queued = queued;
+        }
+    }
+
+    /// Records an assignment the worker is done with.
+    pub fn finish(&self, assignment_id: i64, project_id: i64, project: &str, success: bool, elapsed: Duration) {
+        let mut state = self.board.state.lock().unwrap();
+        let totals = state.projects.entry(project_id).or_default();
+        totals.project = project.to_string();
+        if success {
+            totals.succeeded += 1;
+        } else {
+            totals.failed += 1;
+        }
+
+        if state.recent.len() == RECENT_RESULTS {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(TaskOutcome {
+            assignment_id,
+            project_id,
+            project: project.to_string(),
+            success,
+            elapsed: elapsed.as_secs_f64(),
+            finished: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        });
+    }
+
+    /// Removes the worker from the board.
+    pub fn remove(&self) {
+        self.board.state.lock().unwrap().workers.remove(&self.id);
+    }
+}
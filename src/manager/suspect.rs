@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::data::download::Download;
+use crate::data::project::ProjectPlatform;
+use crate::manager::worker::BinaryError;
+
+/// Assignments a binary must exit unsuccessfully on before it's suspect, since a single failure
+/// may be down to the input.
+const MAX_FAILED_ASSIGNMENTS: usize = 3;
+
+/// A binary, by platform id and download.
+type BinaryKey = (i64, Download);
+
+/// Binaries that failed to run on this host, shared between all worker threads.
+///
+/// Entries are keyed by the platform and the binary's download (url and checksums), so a
+/// binary stops being suspect as soon as the server publishes a different one.
+#[derive(Debug, Clone, Default)]
+pub struct SuspectBinaries {
+    binaries: Arc<Mutex<HashSet<BinaryKey>>>,
+    /// Assignments each binary exited unsuccessfully on.
+    failures: Arc<Mutex<HashMap<BinaryKey, HashSet<i64>>>>,
+}
+
+impl SuspectBinaries {
+    pub fn new() -> SuspectBinaries {
+        SuspectBinaries::default()
+    }
+
+    pub fn add(&self, platform: &ProjectPlatform) -> bool {
+        self.binaries
+            .lock()
+            .unwrap()
+            .insert((platform.platform.id, platform.binary.clone()))
+    }
+
+    /// Records that the binary exited unsuccessfully on an assignment, marking it as suspect once
+    /// it did so on enough different assignments. Returns true if it just became suspect.
+    pub fn add_failure(&self, platform: &ProjectPlatform, assignment_id: i64) -> bool {
+        let key = (platform.platform.id, platform.binary.clone());
+        let mut failures = self.failures.lock().unwrap();
+        let assignments = failures.entry(key).or_default();
+        assignments.insert(assignment_id);
+        if assignments.len() < MAX_FAILED_ASSIGNMENTS {
+            return false;
+        }
+        self.add(platform)
+    }
+
+    /// Records a binary that didn't produce a result on an assignment, returns true if it just
+    /// became suspect.
+    ///
+    /// A binary that crashed or couldn't be launched is suspect right away, one that exited with an
+    /// error only once it did so on enough different assignments.
+    pub fn add_error(&self, platform: &ProjectPlatform, assignment_id: i64, error: &BinaryError) -> bool {
+        if error.is_exit_error() {
+            self.add_failure(platform, assignment_id)
+        } else {
+            self.add(platform)
+        }
+    }
+
+    pub fn contains(&self, platform: &ProjectPlatform) -> bool {
+        self.binaries
+            .lock()
+            .unwrap()
+            .contains(&(platform.platform.id, platform.binary.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    use super::*;
+    use crate::manager::platform::Platform;
+
+    fn get_platform(url: &str) -> ProjectPlatform {
+        let detector = Download::new("https://example.com/detect.sh", Vec::new());
+        let platform = Platform::new(1, "linux-generic", detector);
+        ProjectPlatform::new(platform, Download::new(url, Vec::new()), 1)
+    }
+
+    fn exited(status: i32) -> BinaryError {
+        BinaryError::Exited {
+            status: ExitStatus::from_raw(status),
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+
+    #[test]
+    fn add_failure_needs_enough_different_assignments() {
+        let suspects = SuspectBinaries::new();
+        let platform = get_platform("https://example.com/bin.sh");
+        for _ in 0..MAX_FAILED_ASSIGNMENTS {
+            assert!(!suspects.add_failure(&platform, 1));
+        }
+        assert!(!suspects.contains(&platform));
+
+        for id in 2..MAX_FAILED_ASSIGNMENTS as i64 {
+            assert!(!suspects.add_failure(&platform, id));
+        }
+        assert!(suspects.add_failure(&platform, MAX_FAILED_ASSIGNMENTS as i64));
+        assert!(suspects.contains(&platform));
+        // Only the first failure past the threshold reports the binary as newly suspect
+        assert!(!suspects.add_failure(&platform, 100));
+
+        // A different binary for the same platform starts over
+        assert!(!suspects.contains(&get_platform("https://example.com/bin-v2.sh")));
+    }
+
+    #[test]
+    fn crashes_are_suspect_immediately() {
+        let suspects = SuspectBinaries::new();
+        let platform = get_platform("https://example.com/bin.sh");
+        // Killed by SIGSEGV
+        assert!(suspects.add_error(&platform, 1, &exited(11)));
+        assert!(suspects.contains(&platform));
+
+        let platform = get_platform("https://example.com/launch.sh");
+        let error = BinaryError::Launch(std::io::Error::from_raw_os_error(libc::ENOEXEC));
+        assert!(suspects.add_error(&platform, 1, &error));
+    }
+
+    #[test]
+    fn exit_errors_are_suspect_after_repeated_failures() {
+        let suspects = SuspectBinaries::new();
+        let platform = get_platform("https://example.com/bin.sh");
+        // Exited with code 3
+        let error = exited(3 << 8);
+        for id in 1..MAX_FAILED_ASSIGNMENTS as i64 {
+            assert!(!suspects.add_error(&platform, id, &error));
+            assert!(!suspects.contains(&platform));
+        }
+        assert!(suspects.add_error(&platform, MAX_FAILED_ASSIGNMENTS as i64, &error));
+    }
+}
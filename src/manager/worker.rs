@@ -1,142 +1,752 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use simplelog::{error, info};
+use simplelog::{error, info, warn};
 use tokio::fs;
+use tokio::io::AsyncReadExt;
 use tokio::process::Command;
+use tracing::{info_span, Instrument};
 
+use crate::api::mcathome::assignments::AssignmentInfo;
+use crate::config::ConfigHandle;
 use crate::data::assignment::{Assignment, AssignmentResult};
 use crate::data::project::{Project, ProjectPlatform};
+use crate::history::{History, HistoryEntry, Outcome};
+use crate::hooks::{Event, Hooks};
+use crate::logging::{self, LogFields};
+use crate::manager::batch::{Batch, BatchTask};
+use crate::manager::catalog::{Catalog, CatalogHandle};
+use crate::manager::pause::PauseSwitch;
+use crate::manager::pool::{PoolConfig, ResourceLimits, WorkerPool};
+use crate::manager::scheduler::{Scheduler, TaskSlot};
+use crate::manager::status::{Activity, StatusBoard, WorkerStatusHandle};
+use crate::manager::suspect::SuspectBinaries;
+use crate::metrics::{Metrics, METRICS};
 use crate::MCAtHomeAPI;
-use crate::util::file::set_executable;
+use crate::util::process::{apply_limits, wait_cpu_time};
+
+/// How long to wait for a refresh when an assignment references an unknown project.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Attempts at submitting a result before giving up on it.
+const SUBMIT_ATTEMPTS: u64 = 3;
+
+/// Lines of a failed binary's stderr included in its error.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Why a binary didn't produce a result.
+#[derive(Debug)]
+pub enum BinaryError {
+    /// The binary couldn't be started.
+    Launch(std::io::Error),
+    /// The binary exited unsuccessfully or was killed by a signal.
+    Exited {
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+}
+
+impl BinaryError {
+    /// Returns whether the binary ran and exited with an error code, which may be down to the input.
+    pub fn is_exit_error(&self) -> bool {
+        matches!(self, BinaryError::Exited { .. }) && !self.is_crash()
+    }
+
+    /// Returns whether the binary was killed by a signal, such as when it crashed, rather than
+    /// exiting with an error code.
+    pub fn is_crash(&self) -> bool {
+        match self {
+            BinaryError::Launch(_) => false,
+            #[cfg(not(target_os = "windows"))]
+            BinaryError::Exited { status, .. } => std::os::unix::process::ExitStatusExt::signal(status).is_some(),
+            #[cfg(target_os = "windows")]
+            BinaryError::Exited { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryError::Launch(err) => write!(f, "unable to launch binary: {}", err),
+            BinaryError::Exited { status, stderr, .. } => {
+                write!(f, "binary exited with {}", status)?;
+                let lines = stderr.trim_end().lines().collect::<Vec<&str>>();
+                if !lines.is_empty() {
+                    let tail = &lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..];
+                    write!(f, ", stderr:\n{}", tail.join("\n"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {}
 
 pub struct ProjectWorker {
     pub assignment: Assignment,
+    pub data_dir: PathBuf,
+    /// Number of threads the binary is allowed to use.
+    pub threads: usize,
+    /// Where to report downloads and runs, if anywhere.
+    pub status: Option<WorkerStatusHandle>,
+}
+
+/// State shared by the worker threads of every pool.
+#[derive(Clone)]
+pub struct WorkerContext {
+    pub config: ConfigHandle,
+    pub api: MCAtHomeAPI,
+    pub catalog: CatalogHandle,
+    pub scheduler: Scheduler,
+    pub suspects: SuspectBinaries,
+    pub batch: Batch,
+    pub status: StatusBoard,
+    pub pause: PauseSwitch,
+    pub history: History,
+    pub hooks: Hooks,
 }
 
 pub struct WorkerThread {
     pub id: i32,
+    /// Index of the worker in its pool.
+    pub slot: usize,
+    pub pool: WorkerPool,
+    pub config: ConfigHandle,
     pub api: MCAtHomeAPI,
-    pub projects: Vec<Project>,
-    pub platform_ids: Vec<i64>,
+    pub catalog: CatalogHandle,
+    pub scheduler: Scheduler,
+    pub suspects: SuspectBinaries,
+    pub batch: Batch,
+    pub status: WorkerStatusHandle,
+    pub pause: PauseSwitch,
+    pub history: History,
+    pub hooks: Hooks,
+}
+
+/// What an assignment was reserved before it was fetched.
+struct Reservation {
+    task: BatchTask,
+    /// The project's task slot, if the assignment's project was asked for.
+    slot: Option<TaskSlot>,
+    /// Cores reserved from the pool for the assignment.
+    cores: usize,
 }
 
 impl WorkerThread {
-    pub fn new(id: i32, api: &MCAtHomeAPI, projects: &Vec<Project>, platform_ids: &Vec<i64>) -> WorkerThread {
+    pub fn new(id: i32, slot: usize, pool: &WorkerPool, context: &WorkerContext) -> WorkerThread {
         WorkerThread {
             id,
-            api: api.clone(),
-            projects: projects.clone(),
-            platform_ids: platform_ids.clone(),
+            slot,
+            pool: pool.clone(),
+            config: context.config.clone(),
+            api: context.api.clone(),
+            catalog: context.catalog.clone(),
+            scheduler: context.scheduler.clone(),
+            suspects: context.suspects.clone(),
+            batch: context.batch.clone(),
+            status: context.status.register(id, &pool.get_config().name),
+            pause: context.pause.clone(),
+            history: context.history.clone(),
+            hooks: context.hooks.clone(),
         }
     }
 
     pub async fn run(&self) {
-        info!("Starting worker thread #{}", self.id);
-        loop {
-            let result = self.run_loop().await;
-            if result.is_err() {
-                error!("Worker thread #{} failed: {}", self.id, result.unwrap_err());
-                tokio::time::sleep(Duration::from_secs(60)).await;
+        let fields = LogFields {
+            worker_id: Some(self.id),
+            ..LogFields::default()
+        };
+        logging::scope(fields, self.run_worker()).await
+    }
+
+    async fn run_worker(&self) {
+        let pool = self.pool.get_config().name.clone();
+        info!("Starting worker thread #{} in pool {}", self.id, pool);
+        while !self.batch.is_stopped() && !self.pool.try_retire(self.slot) {
+            if let Err(err) = self.run_loop().await {
+                error!("Worker thread #{} failed: {}", self.id, err);
+                self.status.set(Activity::Idle);
+                tokio::time::sleep(Duration::from_secs(self.config.get().error_sleep)).await;
             }
         }
+        self.status.remove();
+        info!("Stopped worker thread #{} in pool {}", self.id, pool);
     }
 
     async fn run_loop(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let ts = Instant::now();
-        let assignments = self.api.get_assignments(&self.projects).await?;
-        info!("<green><bold>Assigned {} task(s) in {}ms.</>", assignments.len(), ts.elapsed().as_millis());
+        if self.pause.is_paused() {
+            self.status.set(Activity::Paused);
+            self.pause.wait_resumed().await;
+        }
+        self.status.set(Activity::Idle);
+
+        let config = self.config.get();
+        let pool = self.pool.get_config();
+        let catalog = self.catalog.get();
+        let platform_ids = catalog
+            .platform_ids
+            .iter()
+            .filter(|id| pool.allows_platform(&catalog.platforms[id]))
+            .cloned()
+            .collect::<Vec<i64>>();
 
-        if assignments.len() == 0 {
-            info!("<red><bold>No tasks to do. Sleeping for 1 minute.</>");
-            tokio::time::sleep(Duration::from_secs(60)).await;
+        // Only ask for the pool's projects we still have a usable binary for
+        let usable = catalog
+            .projects
+            .iter()
+            .filter(|p| pool.projects.is_allowed(p))
+            .filter(|p| {
+                p.get_platforms(&platform_ids, &catalog.priorities)
+                    .iter()
+                    .any(|platform| !self.suspects.contains(platform))
+            })
+            .collect::<Vec<&Project>>();
+
+        if usable.is_empty() {
+            if self.batch.idle() {
+                return Ok(());
+            }
+            error!(
+                "<red><bold>No project has a usable binary. Sleeping for {}s.</>",
+                config.idle_sleep
+            );
+            tokio::time::sleep(Duration::from_secs(config.idle_sleep)).await;
             return Ok(());
         }
 
-        for assignment in &assignments {
-            let worker = assignment.create_worker();
-            let output = worker.run(&self.platform_ids).await?;
-            self.api.submit_result(&output).await?;
-            info!("<green><bold>Submitted result for assignment {}.</>", assignment.id);
+        // Every project below its task limit in one request, the one furthest behind its share first
+        let projects = self.scheduler.select(usable);
+        if !projects.is_empty() {
+            // Wait for the cores of the project furthest behind its share before asking, so assignments
+            // run as soon as they arrive. Projects needing more cores wait for a later request.
+            let cores = match self.pool.reserve_cores(self.scheduler.get_cores(projects[0])).await {
+                Some(cores) => cores,
+                None => return Ok(()),
+            };
+
+            // Reserve a task of each project before asking, so an assignment never goes over its limit,
+            // and only ask for as many as the batch still takes
+            let mut reserved = Vec::new();
+            let mut names = Vec::new();
+            let fits = |p: &&Project| self.scheduler.get_cores(p).min(pool.size) <= cores.get_cores();
+            for project in projects.into_iter().filter(fits) {
+                let slot = match self.scheduler.try_reserve(project) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                let task = match self.batch.try_start() {
+                    Some(task) => task,
+                    None => break,
+                };
+                reserved.push((slot, task));
+                names.push(project.name.clone());
+            }
+            if reserved.is_empty() {
+                return Ok(());
+            }
+
+            self.status.set(Activity::Fetching { projects: names });
+            let project_ids = reserved.iter().map(|(s, _)| s.get_project_id()).collect::<Vec<i64>>();
+            let ts = Instant::now();
+            let mut assignments = self
+                .api
+                .get_assignments(&project_ids)
+                .instrument(info_span!("get_assignments", projects = project_ids.len()))
+                .await?;
+            self.status.set(Activity::Idle);
+
+            if !assignments.is_empty() {
+                info!(
+                    "<green><bold>Assigned {} task(s) in {}ms.</>",
+                    assignments.len(),
+                    ts.elapsed().as_millis()
+                );
+
+                // Run the assignments of the projects furthest behind their share first
+                let rank = |id| project_ids.iter().position(|p| *p == id).unwrap_or(usize::MAX);
+                assignments.sort_by_key(|info| rank(info.task.project_id));
+                for info in &assignments {
+                    if let Some(project) = catalog.get_project(info.task.project_id) {
+                        Metrics::project_counter(&METRICS.assignments_fetched, project).inc();
+                    }
+                }
+
+                // Hand each assignment its project's reservation, and give back the ones nothing was assigned for
+                let mut queue = Vec::new();
+                let mut unrequested = Vec::new();
+                for info in assignments {
+                    let project_id = info.task.project_id;
+                    match reserved.iter().position(|(s, _)| s.get_project_id() == project_id) {
+                        Some(i) => {
+                            let (slot, task) = reserved.remove(i);
+                            let reservation = Reservation {
+                                task,
+                                slot: Some(slot),
+                                cores: cores.get_cores(),
+                            };
+                            queue.push((info, reservation));
+                        }
+                        None => unrequested.push(info),
+                    }
+                }
+                drop(reserved);
+
+                // The server sent more than was asked for, run it if the batch still takes tasks
+                for info in unrequested {
+                    match self.batch.try_start() {
+                        Some(task) => {
+                            let reservation = Reservation {
+                                task,
+                                slot: None,
+                                cores: cores.get_cores(),
+                            };
+                            queue.push((info, reservation));
+                        }
+                        None => self.reject(&catalog, &info, "the client isn't taking new tasks"),
+                    }
+                }
+
+                let mut queued = queue.len();
+                for (info, reservation) in queue {
+                    queued -= 1;
+                    self.status.set_queued(queued);
+                    let fields = LogFields {
+                        assignment_id: Some(info.id),
+                        project_id: Some(info.task.project_id),
+                        ..logging::get_fields()
+                    };
+                    // The steps of the assignment are traced as children of this span
+                    let span = info_span!("assignment", assignment_id = info.id, project_id = info.task.project_id);
+                    let id = info.id;
+                    // The assignment is already recorded when it fails, the rest of the queue still runs
+                    let result = logging::scope(
+                        fields,
+                        self.run_assignment(&pool, catalog.clone(), &platform_ids, info, reservation)
+                            .instrument(span),
+                    )
+                    .await;
+                    if let Err(err) = result {
+                        error!("<red>Assignment {} failed: {}</>", id, err);
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        if self.batch.idle() {
+            return Ok(());
         }
+        info!("<red><bold>No tasks to do. Sleeping for {}s.</>", config.idle_sleep);
+        tokio::time::sleep(Duration::from_secs(config.idle_sleep)).await;
         Ok(())
     }
-}
 
-impl ProjectWorker {
-    fn get_platform(&self, platforms: &Vec<i64>) -> Result<&ProjectPlatform, Box<dyn std::error::Error>> {
-        for platform in platforms {
-            if let Some(platform) = self.assignment.project.platforms.get(platform) {
-                return Ok(platform);
+    async fn run_assignment(
+        &self,
+        pool: &PoolConfig,
+        mut catalog: Arc<Catalog>,
+        platform_ids: &[i64],
+        info: AssignmentInfo,
+        reservation: Reservation,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Reservation { task, slot, cores } = reservation;
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        if catalog.get_project(info.task.project_id).is_none() {
+            warn!(
+                "<yellow>Assignment {} is for unknown project {}, refreshing projects...</>",
+                info.id, info.task.project_id
+            );
+            catalog = self.catalog.refresh(REFRESH_TIMEOUT).await;
+        }
+
+        let project = match catalog.get_project(info.task.project_id) {
+            Some(project) => project.clone(),
+            None => {
+                error!(
+                    "<red>Skipping assignment {}: project {} not found</>",
+                    info.id, info.task.project_id
+                );
+                let project = info.task.project_id.to_string();
+                let mut entry = HistoryEntry::new(info.id, info.task.project_id, &project, started_at, Outcome::Failed);
+                entry.error = Some("project not found".to_string());
+                self.finish(task, entry, started);
+                return Ok(());
+            }
+        };
+
+        // Assignments the server sent without being asked take a slot now, if the project has one left
+        let slot = match slot.or_else(|| self.scheduler.try_reserve(&project)) {
+            Some(slot) => slot,
+            None => {
+                error!(
+                    "<red>Skipping assignment {}: {} is running its maximum number of tasks</>",
+                    info.id, project.name
+                );
+                let mut entry = HistoryEntry::new(info.id, project.id, &project.name, started_at, Outcome::Failed);
+                entry.error = Some("project is running its maximum number of tasks".to_string());
+                self.finish(task, entry, started);
+                return Ok(());
+            }
+        };
+
+        let cores = self.scheduler.get_cores(&project).min(cores);
+
+        let assignment = Assignment::new(info.id, project, info.task.input_data);
+        let mut worker = assignment.create_worker(&self.config.get().data_dir);
+        worker.threads = cores;
+        worker.status = Some(self.status.clone());
+        self.hooks.fire(Event::AssignmentStarted {
+            assignment_id: assignment.id,
+            project_id: assignment.project.id,
+            project: assignment.project.name.clone(),
+        });
+        let result = worker
+            .run(platform_ids, &catalog.priorities, &self.suspects, &pool.limits)
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                let outcome = if is_timeout(err.as_ref()) {
+                    Metrics::project_counter(&METRICS.assignments_timed_out, &assignment.project).inc();
+                    Outcome::TimedOut
+                } else {
+                    Metrics::project_counter(&METRICS.assignments_failed, &assignment.project).inc();
+                    Outcome::Failed
+                };
+                let mut entry = self.create_entry(&assignment, started_at, outcome);
+                entry.error = Some(err.to_string());
+                self.finish(task, entry, started);
+                return Err(err);
+            }
+        };
+        slot.complete(Duration::from_nanos(output.execution_time as u64) * cores as u32);
+
+        self.status.set(Activity::Submitting {
+            assignment_id: assignment.id,
+            project_id: assignment.project.id,
+            project: assignment.project.name.clone(),
+        });
+        let submission = self
+            .submit(&output)
+            .instrument(info_span!("submit_result", assignment_id = output.id))
+            .await;
+        let outcome = if submission.is_ok() { Outcome::Succeeded } else { Outcome::Failed };
+        let mut entry = self.create_entry(&assignment, started_at, outcome);
+        entry.set_result(&output);
+        match submission {
+            Ok(id) => {
+                Metrics::project_counter(&METRICS.assignments_completed, &assignment.project).inc();
+                info!("<green><bold>Submitted result for assignment {}.</>", assignment.id);
+                entry.submission_id = Some(id);
+                self.finish(task, entry, started);
+                Ok(())
+            }
+            Err(err) => {
+                Metrics::project_counter(&METRICS.assignments_failed, &assignment.project).inc();
+                entry.error = Some(format!("unable to submit the result: {}", err));
+                self.finish(task, entry, started);
+                Err(err)
             }
         }
+    }
 
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "platform not found",
-        )))
+    fn create_entry(&self, assignment: &Assignment, started_at: SystemTime, outcome: Outcome) -> HistoryEntry {
+        let project = &assignment.project;
+        HistoryEntry::new(assignment.id, project.id, &project.name, started_at, outcome)
     }
 
-    pub async fn prepare_binary(&self, platform: &ProjectPlatform) -> Result<Command, Box<dyn std::error::Error>> {
-        let dir = Path::new("projects")
-            .join(&self.assignment.project.name)
-            .join("bin");
+    /// Records an assignment the client won't run as failed, so it still counts toward the exit code.
+    fn reject(&self, catalog: &Catalog, info: &AssignmentInfo, error: &str) {
+        let project_id = info.task.project_id;
+        let project = match catalog.get_project(project_id) {
+            Some(project) => project.name.clone(),
+            None => project_id.to_string(),
+        };
+        warn!("<yellow>Not running assignment {}, {}</>", info.id, error);
+        let mut entry = HistoryEntry::new(info.id, project_id, &project, SystemTime::now(), Outcome::Failed);
+        entry.error = Some(error.to_string());
+        self.finish(self.batch.force_start(), entry, Instant::now());
+    }
 
-        if !&dir.exists() {
-            fs::create_dir_all(&dir)
-                .await
-                .expect("failed to create platforms directory");
+    /// Records the end of an assignment on the status board, in the history and in the batch.
+    fn finish(&self, task: BatchTask, entry: HistoryEntry, started: Instant) {
+        let success = entry.outcome == Outcome::Succeeded;
+        self.status.finish(
+            entry.assignment_id,
+            entry.project_id,
+            &entry.project,
+            success,
+            started.elapsed(),
+        );
+        self.history.record(&entry);
+        self.hooks.fire(if success {
+            Event::AssignmentFinished { assignment: entry }
+        } else {
+            Event::AssignmentFailed { assignment: entry }
+        });
+        task.finish(success);
+    }
+
+    /// Submits a result, retrying unless the server rejected it, and returns its submission id.
+    async fn submit(&self, output: &AssignmentResult) -> Result<i64, Box<dyn std::error::Error>> {
+        let mut attempt = 1;
+        loop {
+            match self.api.submit_result(output).await {
+                Ok(response) => return Ok(response.id),
+                Err(err) if attempt < SUBMIT_ATTEMPTS && !err.status().map(|s| s.is_client_error()).unwrap_or(false) => {
+                    warn!(
+                        "<yellow>Unable to submit result for assignment {}, retrying: {}</>",
+                        output.id, err
+                    );
+                    METRICS.submit_retries.inc();
+                    tokio::time::sleep(Duration::from_secs(5 * attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl ProjectWorker {
+    fn report(&self, activity: Activity) {
+        if let Some(status) = &self.status {
+            status.set(activity);
         }
+    }
 
-        let path = dir.join(platform.binary.get_filename());
-        platform.binary.download_to_file(&path).await.unwrap();
-        set_executable(&path).await;
+    fn get_platforms(
+        &self,
+        platforms: &[i64],
+        priorities: &HashMap<i64, i32>,
+        suspects: &SuspectBinaries,
+    ) -> Vec<&ProjectPlatform> {
+        self.assignment
+            .project
+            .get_platforms(platforms, priorities)
+            .into_iter()
+            .filter(|platform| !suspects.contains(platform))
+            .collect()
+    }
 
+    pub async fn prepare_binary(&self, platform: &ProjectPlatform) -> Result<Command, Box<dyn std::error::Error>> {
+        let progress = |downloaded, total| {
+            if let Some(status) = &self.status {
+                status.set_download(downloaded, total);
+            }
+        };
+        let path = self
+            .assignment
+            .project
+            .download_binary(platform, &self.data_dir, &progress)
+            .await?;
         Ok(platform.binary.get_command(&path))
     }
 
     pub async fn prepare_input(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
-        let dir = Path::new("projects")
-            .join(&self.assignment.project.name)
-            .join("inputs");
+        let dir = self.assignment.project.get_dir(&self.data_dir).join("inputs");
 
         if !&dir.exists() {
-            fs::create_dir_all(&dir)
-                .await
-                .expect("failed to create platforms directory");
+            fs::create_dir_all(&dir).await?;
         }
 
         let path = dir.join(format!("{}.bin", self.assignment.id));
-        fs::write(&path, &self.assignment.input_data).await.unwrap();
+        fs::write(&path, &self.assignment.input_data).await?;
         Ok(path)
     }
 
-    pub async fn run(&self, platform_ids: &Vec<i64>) -> Result<AssignmentResult, Box<dyn std::error::Error>> {
+    /// Runs the assignment with the most preferred binary, falling back to the next
+    /// compatible one whenever a binary fails to launch or crashes.
+    ///
+    /// A binary exiting with an error code fails the assignment, since the input may be at fault.
+    pub async fn run(
+        &self,
+        platform_ids: &[i64],
+        priorities: &HashMap<i64, i32>,
+        suspects: &SuspectBinaries,
+        limits: &ResourceLimits,
+    ) -> Result<AssignmentResult, Box<dyn std::error::Error>> {
         info!("Running assignment {}", self.assignment.id);
-        let platform = self.get_platform(platform_ids)?;
-        let mut command = self.prepare_binary(platform).await?;
-        let input_path = self.prepare_input().await?;
+        let input_path = self
+            .prepare_input()
+            .instrument(info_span!("prepare_input", assignment_id = self.assignment.id))
+            .await?;
+        self.run_input(&input_path, platform_ids, priorities, suspects, limits).await
+    }
+
+    /// Runs the binaries on an input file that's already on disk, see [`ProjectWorker::run`].
+    pub async fn run_input(
+        &self,
+        input_path: &Path,
+        platform_ids: &[i64],
+        priorities: &HashMap<i64, i32>,
+        suspects: &SuspectBinaries,
+        limits: &ResourceLimits,
+    ) -> Result<AssignmentResult, Box<dyn std::error::Error>> {
+        let platforms = self.get_platforms(platform_ids, priorities, suspects);
+        if platforms.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "platform not found").into());
+        }
 
+        // The binary's own error says more than a generic one when every binary failed
+        let mut last_error = None;
+        for platform in platforms {
+            self.report(Activity::Downloading {
+                assignment_id: self.assignment.id,
+                project_id: self.assignment.project.id,
+                project: self.assignment.project.name.clone(),
+                platform: platform.platform.name.clone(),
+            });
+            let span = info_span!(
+                "prepare_binary",
+                assignment_id = self.assignment.id,
+                platform = platform.platform.name.as_str()
+            );
+            let mut command = match self.prepare_binary(platform).instrument(span).await {
+                Ok(command) => command,
+                Err(err) => {
+                    error!(
+                        "Unable to prepare {} binary for assignment {}: {}",
+                        platform.platform.name, self.assignment.id, err
+                    );
+                    let error = format!("unable to prepare the {} binary: {}", platform.platform.name, err);
+                    last_error = Some(error.into());
+                    continue;
+                }
+            };
+
+            self.report(Activity::Running {
+                assignment_id: self.assignment.id,
+                project_id: self.assignment.project.id,
+                project: self.assignment.project.name.clone(),
+                platform: platform.platform.name.clone(),
+            });
+            let span = info_span!(
+                "execute",
+                assignment_id = self.assignment.id,
+                platform = platform.platform.name.as_str()
+            );
+            match self.execute(&mut command, input_path, limits).instrument(span).await {
+                Ok(mut result) => {
+                    result.platform = Some(platform.platform.name.clone());
+                    result.checksum = platform.binary.get_checksum();
+                    return Ok(result);
+                }
+                Err(err) => {
+                    let failure = match err.downcast_ref::<BinaryError>() {
+                        Some(failure) => failure,
+                        None => return Err(err),
+                    };
+                    error!(
+                        "<red>{} binary failed on assignment {}: {}</>",
+                        platform.platform.name, self.assignment.id, err
+                    );
+                    let exit_error = failure.is_exit_error();
+                    if suspects.add_error(platform, self.assignment.id, failure) {
+                        error!(
+                            "<red>Marking {} binary for {} as suspect on this host{}</>",
+                            platform.platform.name,
+                            self.assignment.project.name,
+                            if exit_error { " after repeated failures" } else { "" }
+                        );
+                    }
+                    // The input may be at fault, so other binaries aren't tried
+                    if exit_error {
+                        return Err(err);
+                    }
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            Box::new(std::io::Error::other(format!(
+                "no compatible binary could run assignment {}",
+                self.assignment.id
+            )))
+        }))
+    }
+
+    async fn execute(
+        &self,
+        command: &mut Command,
+        input_path: &Path,
+        limits: &ResourceLimits,
+    ) -> Result<AssignmentResult, Box<dyn std::error::Error>> {
         command.arg("--input");
-        command.arg(&input_path.canonicalize()?.to_str().unwrap());
+        command.arg(input_path.canonicalize()?);
+        command.env("DICC_THREADS", self.threads.to_string());
+        command.env("OMP_NUM_THREADS", self.threads.to_string());
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        command.kill_on_drop(true);
+        apply_limits(command, limits);
 
         let start = Instant::now();
-        let output = command.output().await?;
-        if output.status.success() {
+        let (status, stdout, stderr, cpu_time) = match limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run_command(command))
+                .await
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("binary timed out after {}s", timeout.as_secs()),
+                    )
+                })??,
+            None => run_command(command).await?,
+        };
+        if status.success() {
             info!("Assignment {} finished successfully", self.assignment.id);
-            Ok(AssignmentResult::new(
+            Metrics::project_histogram(&METRICS.execution_seconds, &self.assignment.project)
+                .observe(start.elapsed().as_secs_f64());
+            let mut result = AssignmentResult::new(
                 self.assignment.id,
-                String::from_utf8(output.stdout)?,
-                String::from_utf8(output.stderr)?,
-                output.status.code().unwrap(),
+                String::from_utf8(stdout)?,
+                String::from_utf8(stderr)?,
+                status.code().unwrap(),
                 start.elapsed().as_nanos(),
-            ))
+            );
+            result.cpu_time = cpu_time;
+            Ok(result)
         } else {
-            error!("assignment {} failed", self.assignment.id);
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "failed to run binary",
-            )))
+            Err(Box::new(BinaryError::Exited {
+                status,
+                stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            }))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Runs a command to completion, returning its exit status, output and CPU time.
+async fn run_command(
+    command: &mut Command,
+) -> Result<(ExitStatus, Vec<u8>, Vec<u8>, Option<Duration>), Box<dyn std::error::Error>> {
+    let mut child = command.spawn().map_err(BinaryError::Launch)?;
+    let pid = child.id();
+    let cpu_time = tokio::task::spawn_blocking(move || pid.and_then(wait_cpu_time));
+
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    tokio::try_join!(stdout_pipe.read_to_end(&mut stdout), stderr_pipe.read_to_end(&mut stderr))?;
+
+    // Waiting reaps the process, after which its CPU time is gone
+    let cpu_time = cpu_time.await.ok().flatten();
+    let status = child.wait().await?;
+    Ok((status, stdout, stderr, cpu_time))
+}
+
+fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .map(|err| err.kind() == std::io::ErrorKind::TimedOut)
+        .unwrap_or(false)
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use simplelog::{error, info};
+
+use crate::data::project::Project;
+use crate::manager::status::StatusBoard;
+
+/// Metrics of the client, shared by everything it runs.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Upper bounds of the execution time buckets, in seconds.
+const EXECUTION_BUCKETS: [f64; 11] = [1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0];
+
+const PROJECT_LABELS: [&str; 2] = ["project_id", "project"];
+
+pub struct Metrics {
+    registry: Registry,
+    pub assignments_fetched: IntCounterVec,
+    pub assignments_completed: IntCounterVec,
+    pub assignments_failed: IntCounterVec,
+    pub assignments_timed_out: IntCounterVec,
+    pub submit_retries: IntCounter,
+    pub download_bytes: IntCounter,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+    pub execution_seconds: HistogramVec,
+    pub feeder_latency_seconds: Histogram,
+    pub workers: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let counter = |name: &str, help: &str| {
+            IntCounterVec::new(Opts::new(name, help), &PROJECT_LABELS).expect("invalid metric")
+        };
+
+        let metrics = Metrics {
+            registry: Registry::new(),
+            assignments_fetched: counter("dicc_assignments_fetched_total", "Assignments received from the feeder."),
+            assignments_completed: counter(
+                "dicc_assignments_completed_total",
+                "Assignments run and submitted successfully.",
+            ),
+            assignments_failed: counter(
+                "dicc_assignments_failed_total",
+                "Assignments no binary could run, or whose result couldn't be submitted.",
+            ),
+            assignments_timed_out: counter(
+                "dicc_assignments_timed_out_total",
+                "Assignments whose binary was killed after the pool's timeout.",
+            ),
+            submit_retries: IntCounter::new("dicc_submit_retries_total", "Retried result submissions.")
+                .expect("invalid metric"),
+            download_bytes: IntCounter::new("dicc_download_bytes_total", "Bytes of detectors and binaries downloaded.")
+                .expect("invalid metric"),
+            cache_hits: IntCounter::new(
+                "dicc_download_cache_hits_total",
+                "Detectors and binaries found in the cache.",
+            )
+            .expect("invalid metric"),
+            cache_misses: IntCounter::new(
+                "dicc_download_cache_misses_total",
+                "Detectors and binaries missing from the cache or failing verification.",
+            )
+            .expect("invalid metric"),
+            execution_seconds: HistogramVec::new(
+                HistogramOpts::new("dicc_execution_seconds", "Wall time of successful binary runs.")
+                    .buckets(EXECUTION_BUCKETS.to_vec()),
+                &PROJECT_LABELS,
+            )
+            .expect("invalid metric"),
+            feeder_latency_seconds: Histogram::with_opts(HistogramOpts::new(
+                "dicc_feeder_latency_seconds",
+                "Time taken by the feeder to hand out assignments.",
+            ))
+            .expect("invalid metric"),
+            workers: IntGaugeVec::new(Opts::new("dicc_workers", "Worker threads by state."), &["state"])
+                .expect("invalid metric"),
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.assignments_fetched.clone())).unwrap();
+        registry.register(Box::new(metrics.assignments_completed.clone())).unwrap();
+        registry.register(Box::new(metrics.assignments_failed.clone())).unwrap();
+        registry.register(Box::new(metrics.assignments_timed_out.clone())).unwrap();
+        registry.register(Box::new(metrics.submit_retries.clone())).unwrap();
+        registry.register(Box::new(metrics.download_bytes.clone())).unwrap();
+        registry.register(Box::new(metrics.cache_hits.clone())).unwrap();
+        registry.register(Box::new(metrics.cache_misses.clone())).unwrap();
+        registry.register(Box::new(metrics.execution_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.feeder_latency_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.workers.clone())).unwrap();
+        metrics
+    }
+
+    /// Returns the counter of a project.
+    pub fn project_counter(counter: &IntCounterVec, project: &Project) -> IntCounter {
+        counter.with_label_values(&[&project.id.to_string(), &project.name])
+    }
+
+    /// Returns the histogram of a project.
+    pub fn project_histogram(histogram: &HistogramVec, project: &Project) -> Histogram {
+        histogram.with_label_values(&[&project.id.to_string(), &project.name])
+    }
+
+    /// Sets the worker gauges from the workers' current activity.
+    pub fn update_workers(&self, status: &StatusBoard) {
+        let mut states = BTreeMap::new();
+        for state in ["idle", "paused", "fetching", "downloading", "running", "submitting", "dead"] {
+            states.insert(state, 0);
+        }
+        for worker in status.get_workers() {
+            *states.entry(worker.activity.get_name()).or_default() += 1;
+        }
+        for (state, count) in states {
+            self.workers.with_label_values(&[state]).set(count);
+        }
+    }
+
+    /// Encodes every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Serves `GET /metrics` for Prometheus.
+pub struct MetricsServer {
+    incoming: AddrIncoming,
+}
+
+impl MetricsServer {
+    pub fn bind(address: &SocketAddr) -> Result<MetricsServer, Box<dyn std::error::Error>> {
+        let incoming = AddrIncoming::bind(address)
+            .map_err(|e| format!("unable to serve metrics on {}: {}", address, e))?;
+        Ok(MetricsServer { incoming })
+    }
+
+    pub async fn run(self, status: StatusBoard) {
+        info!("<green><bold>Serving metrics on http://{}/metrics</>", self.incoming.local_addr());
+        let service = make_service_fn(move |_| {
+            let status = status.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let status = status.clone();
+                    async move { Ok::<_, Infallible>(handle(request, &status)) }
+                }))
+            }
+        });
+
+        if let Err(err) = Server::builder(self.incoming).serve(service).await {
+            error!("<red>Metrics endpoint failed: {}</>", err);
+        }
+    }
+}
+
+fn handle(request: Request<Body>, status: &StatusBoard) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("not found"))
+            .unwrap();
+    }
+
+    METRICS.update_workers(status);
+    match METRICS.encode() {
+        Ok(body) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
+    }
+}
@@ -0,0 +1,406 @@
+//! Periodic status reports POSTed to a collector, so a team can watch a fleet of clients.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+
+use crate::config::ConfigHandle;
+use crate::history::{History, HistoryFilter, Outcome};
+use crate::manager::catalog::CatalogHandle;
+use crate::manager::pause::PauseSwitch;
+use crate::manager::scheduler::Scheduler;
+use crate::manager::status::{Activity, StatusBoard};
+use crate::manager::worker::WorkerContext;
+use crate::stats::{self, ProjectStats};
+use crate::util::secret::Secret;
+
+/// Name of the file in the data directory holding the generated host id.
+const HOST_ID_FILE: &str = "host-id";
+
+/// Settings of the status reports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportSettings {
+    /// URL of the collector the reports are POSTed to as JSON. Nothing is reported if unset.
+    pub url: Option<String>,
+    /// Bearer token sent to the collector.
+    pub token: Option<Secret>,
+    /// Seconds between two reports.
+    pub interval: u64,
+    /// Reports kept while the collector is unreachable, the oldest are dropped first.
+    pub max_buffered: usize,
+    /// Id of this host in the reports [default: a random id generated once per data directory].
+    pub host_id: Option<String>,
+}
+
+impl Default for ReportSettings {
+    fn default() -> ReportSettings {
+        ReportSettings {
+            url: None,
+            token: None,
+            interval: 300,
+            max_buffered: 100,
+            host_id: None,
+        }
+    }
+}
+
+impl ReportSettings {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(url) = &self.url {
+            reqwest::Url::parse(url).map_err(|e| format!("invalid report.url `{}`: {}", url, e))?;
+        }
+        if self.interval == 0 {
+            return Err("report.interval must be at least 1 second".into());
+        }
+        Ok(())
+    }
+}
+
+/// What the client did since its previous report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub host_id: String,
+    pub hostname: String,
+    pub version: String,
+    /// RFC 3339 time of the report.
+    pub time: String,
+    /// Seconds since the client started.
+    pub uptime: u64,
+    /// Platforms detected on this host.
+    pub platforms: Vec<String>,
+    pub workers: usize,
+    /// Workers fetching, running or submitting an assignment at the time of the report.
+    pub busy_workers: usize,
+    /// Share of the workers' time spent on assignments during the period.
+    pub utilization: f64,
+    pub paused: bool,
+    /// Seconds since the previous report, which the counts and statistics cover.
+    pub period: u64,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    /// Tasks of each project running at the time of the report, by project id.
+    pub running: BTreeMap<i64, usize>,
+    /// Statistics of each project with assignments finished during the period.
+    pub projects: Vec<ProjectStats>,
+}
+
+/// Sends a [`StatusReport`] to the configured collector on every interval.
+pub struct StatusReporter {
+    config: ConfigHandle,
+    catalog: CatalogHandle,
+    scheduler: Scheduler,
+    status: StatusBoard,
+    pause: PauseSwitch,
+    history: History,
+    client: reqwest::Client,
+    host_id: String,
+}
+
+impl StatusReporter {
+    pub fn new(context: &WorkerContext, data_dir: &Path) -> Result<StatusReporter, Box<dyn std::error::Error>> {
+        let host_id = match &context.config.get().report.host_id {
+            Some(host_id) => host_id.clone(),
+            None => get_host_id(data_dir)?,
+        };
+        Ok(StatusReporter {
+            config: context.config.clone(),
+            catalog: context.catalog.clone(),
+            scheduler: context.scheduler.clone(),
+            status: context.status.clone(),
+            pause: context.pause.clone(),
+            history: context.history.clone(),
+            client: reqwest::Client::new(),
+            host_id,
+        })
+    }
+
+    pub async fn run(&self) {
+        let mut queue = ReportQueue::new(self.client.clone());
+        let mut previous = SystemTime::now();
+        loop {
+            let settings = self.config.get().report.clone();
+            let url = match &settings.url {
+                Some(url) => url,
+                None => {
+                    // Reports may be enabled by a config reload
+                    queue.clear();
+                    previous = SystemTime::now();
+                    tokio::time::sleep(Duration::from_secs(settings.interval)).await;
+                    continue;
+                }
+            };
+
+            tokio::time::sleep(Duration::from_secs(settings.interval)).await;
+            let now = SystemTime::now();
+            match self.create_report(previous, now).await {
+                Ok(report) => queue.push(report, settings.max_buffered),
+                Err(err) => warn!("<yellow>Unable to create status report: {}</>", err),
+            }
+            previous = now;
+            queue.flush(url, settings.token.as_ref()).await;
+        }
+    }
+
+    async fn create_report(&self, since: SystemTime, now: SystemTime) -> Result<StatusReport, String> {
+        let filter = HistoryFilter {
+            since: Some(since),
+            until: Some(now),
+            ..HistoryFilter::default()
+        };
+        let history = self.history.clone();
+        let entries = tokio::task::spawn_blocking(move || history.query(&filter).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| e.to_string())??;
+
+        let period = now.duration_since(since).unwrap_or_default().as_secs_f64();
+        let workers = self.status.get_workers();
+        let busy = entries.iter().filter_map(|e| e.get_elapsed()).sum::<f64>();
+        let count = |outcome| entries.iter().filter(|e| e.outcome == outcome).count();
+
+        let catalog = self.catalog.get();
+        let mut platforms = catalog
+            .platforms
+            .values()
+            .map(|p| p.name.clone())
+            .collect::<Vec<String>>();
+        platforms.sort();
+
+        Ok(StatusReport {
+            host_id: self.host_id.clone(),
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            time: humantime::format_rfc3339_seconds(now).to_string(),
+            uptime: self.status.get_uptime().as_secs(),
+            platforms,
+            workers: workers.len(),
+            busy_workers: workers
+                .iter()
+                .filter(|w| !matches!(w.activity, Activity::Idle | Activity::Paused | Activity::Dead { .. }))
+                .count(),
+            utilization: if workers.is_empty() || period <= 0.0 {
+                0.0
+            } else {
+                (busy / (workers.len() as f64 * period)).min(1.0)
+            },
+            paused: self.pause.is_paused(),
+            period: period as u64,
+            succeeded: count(Outcome::Succeeded),
+            failed: count(Outcome::Failed),
+            timed_out: count(Outcome::TimedOut),
+            running: self
+                .scheduler
+                .get_usage()
+                .into_iter()
+                .filter(|(_, usage)| usage.running > 0)
+                .map(|(id, usage)| (id, usage.running))
+                .collect(),
+            projects: stats::aggregate(&entries, false),
+        })
+    }
+
+}
+
+/// Reports waiting to be sent, kept while the collector is unreachable.
+struct ReportQueue {
+    client: reqwest::Client,
+    reports: VecDeque<StatusReport>,
+    reachable: bool,
+}
+
+impl ReportQueue {
+    fn new(client: reqwest::Client) -> ReportQueue {
+        ReportQueue {
+            client,
+            reports: VecDeque::new(),
+            reachable: true,
+        }
+    }
+
+    /// Queues a report, dropping the oldest ones beyond `max_buffered`.
+    fn push(&mut self, report: StatusReport, max_buffered: usize) {
+        self.reports.push_back(report);
+        while self.reports.len() > max_buffered {
+            self.reports.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.reports.clear();
+    }
+
+    /// Sends the queued reports oldest first, so the collector sees them in order, until one fails.
+    async fn flush(&mut self, url: &str, token: Option<&Secret>) {
+        while let Some(report) = self.reports.front() {
+            match self.send(url, token, report).await {
+                Ok(()) => {
+                    self.reports.pop_front();
+                }
+                Err(err) => {
+                    if self.reachable {
+                        warn!(
+                            "<yellow>Unable to send status report, buffering until the collector is back: {}</>",
+                            err
+                        );
+                    }
+                    self.reachable = false;
+                    return;
+                }
+            }
+        }
+        if !self.reachable {
+            info!("<green>Sent the buffered status reports.</>");
+            self.reachable = true;
+        }
+    }
+
+    async fn send(&self, url: &str, token: Option<&Secret>, report: &StatusReport) -> Result<(), reqwest::Error> {
+        let mut request = self.client.post(url).json(report).timeout(Duration::from_secs(30));
+        if let Some(token) = token {
+            request = request.bearer_auth(token.expose());
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Returns the id generated for the data directory, generating it on first use.
+fn get_host_id(data_dir: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let path = data_dir.join(HOST_ID_FILE);
+    if let Ok(host_id) = std::fs::read_to_string(&path) {
+        if !host_id.trim().is_empty() {
+            return Ok(host_id.trim().to_string());
+        }
+    }
+
+    let host_id = uuid::Uuid::new_v4().to_string();
+    std::fs::write(&path, format!("{}\n", host_id))
+        .map_err(|e| format!("unable to write host id to {}: {}", path.display(), e))?;
+    Ok(host_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+
+    use super::*;
+
+    fn report(time: &str) -> StatusReport {
+        StatusReport {
+            host_id: "host".to_string(),
+            hostname: "hostname".to_string(),
+            version: "0.0.0".to_string(),
+            time: time.to_string(),
+            uptime: 0,
+            platforms: Vec::new(),
+            workers: 1,
+            busy_workers: 0,
+            utilization: 0.0,
+            paused: false,
+            period: 300,
+            succeeded: 0,
+            failed: 0,
+            timed_out: 0,
+            running: BTreeMap::new(),
+            projects: Vec::new(),
+        }
+    }
+
+    fn times(queue: &ReportQueue) -> Vec<String> {
+        queue.reports.iter().map(|r| r.time.clone()).collect()
+    }
+
+    /// A collector answering 503 while `down` is set, recording the time and token of each report.
+    struct Collector {
+        address: SocketAddr,
+        down: Arc<AtomicBool>,
+        received: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl Collector {
+        fn start() -> Collector {
+            let down = Arc::new(AtomicBool::new(true));
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let (state, log) = (down.clone(), received.clone());
+            let make_service = make_service_fn(move |_| {
+                let (down, received) = (state.clone(), log.clone());
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let (down, received) = (down.clone(), received.clone());
+                        async move {
+                            if down.load(Ordering::SeqCst) {
+                                let mut response = Response::new(Body::empty());
+                                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                                return Ok::<_, Infallible>(response);
+                            }
+                            let token = request
+                                .headers()
+                                .get(hyper::header::AUTHORIZATION)
+                                .and_then(|value| value.to_str().ok())
+                                .unwrap_or_default()
+                                .to_string();
+                            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                            let report = serde_json::from_slice::<StatusReport>(&body).unwrap();
+                            received.lock().unwrap().push((report.time, token));
+                            Ok(Response::new(Body::empty()))
+                        }
+                    }))
+                }
+            });
+            let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+            let address = server.local_addr();
+            tokio::spawn(server);
+            Collector { address, down, received }
+        }
+    }
+
+    #[test]
+    fn push_drops_the_oldest_reports() {
+        let mut queue = ReportQueue::new(reqwest::Client::new());
+        for time in ["1", "2", "3"] {
+            queue.push(report(time), 2);
+        }
+        assert_eq!(times(&queue), vec!["2", "3"]);
+
+        queue.push(report("4"), 0);
+        assert!(queue.reports.is_empty());
+    }
+
+    #[tokio::test]
+    async fn buffered_reports_are_sent_once_the_collector_is_back() {
+        let collector = Collector::start();
+        let url = format!("http://{}/reports", collector.address);
+        let token = Secret::new("token");
+        let mut queue = ReportQueue::new(reqwest::Client::new());
+
+        for time in ["1", "2", "3"] {
+            queue.push(report(time), 2);
+            queue.flush(&url, Some(&token)).await;
+        }
+        assert!(!queue.reachable);
+        assert_eq!(times(&queue), vec!["2", "3"]);
+        assert!(collector.received.lock().unwrap().is_empty());
+
+        collector.down.store(false, Ordering::SeqCst);
+        queue.push(report("4"), 2);
+        queue.flush(&url, Some(&token)).await;
+        assert!(queue.reachable);
+        assert!(queue.reports.is_empty());
+        let received = collector.received.lock().unwrap().clone();
+        let expected = ["3", "4"]
+            .iter()
+            .map(|time| (time.to_string(), "Bearer token".to_string()))
+            .collect::<Vec<(String, String)>>();
+        assert_eq!(received, expected);
+    }
+}
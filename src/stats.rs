@@ -0,0 +1,269 @@
+//! Throughput and reliability of each project on this host, aggregated from the history.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use simplelog::error;
+
+use crate::history::{History, HistoryEntry, HistoryFilter, Outcome};
+use crate::manager::scheduler::Scheduler;
+
+/// How often the scheduler's efficiency of each project is recomputed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How far back the history is read to compute the scheduler's efficiency of each project.
+const EFFICIENCY_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Assignments a project needs in the window before its efficiency is trusted.
+const MIN_ASSIGNMENTS: usize = 5;
+
+/// Lowest efficiency given to a project, so a failing project is still retried now and then.
+const MIN_EFFICIENCY: f64 = 0.1;
+
+/// Statistics of a project, or of one of its platform binaries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectStats {
+    pub project_id: i64,
+    pub project: String,
+    /// Platform of the binary, when grouped by platform. Assignments that failed before a binary
+    /// produced a result have none.
+    pub platform: Option<String>,
+    pub assignments: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    /// Assignments succeeded per hour a worker spent on the project, from start to submission.
+    pub per_hour: f64,
+    /// Mean seconds the binary ran for on succeeded assignments.
+    pub mean_runtime: Option<f64>,
+    /// 95th percentile of the seconds the binary ran for on succeeded assignments.
+    pub p95_runtime: Option<f64>,
+    /// Share of the assignments that failed, not counting timeouts.
+    pub failure_rate: f64,
+    /// Share of the assignments that timed out.
+    pub timeout_rate: f64,
+}
+
+impl ProjectStats {
+    /// Returns the share of assignments that succeeded, or `None` with too few assignments to tell.
+    pub fn get_efficiency(&self) -> Option<f64> {
+        if self.assignments < MIN_ASSIGNMENTS {
+            return None;
+        }
+        Some((self.succeeded as f64 / self.assignments as f64).max(MIN_EFFICIENCY))
+    }
+}
+
+/// Aggregates `entries` by project, and by the platform of the binary with `by_platform`.
+///
+/// Projects are sorted by id, then by platform.
+pub fn aggregate(entries: &[HistoryEntry], by_platform: bool) -> Vec<ProjectStats> {
+    let mut groups: BTreeMap<(i64, Option<String>), Vec<&HistoryEntry>> = BTreeMap::new();
+    for entry in entries {
+        let platform = if by_platform { entry.platform.clone() } else { None };
+        groups.entry((entry.project_id, platform)).or_default().push(entry);
+    }
+
+    groups
+        .into_iter()
+        .map(|((project_id, platform), entries)| {
+            let count = |outcome| entries.iter().filter(|e| e.outcome == outcome).count();
+            let succeeded = count(Outcome::Succeeded);
+            let failed = count(Outcome::Failed);
+            let timed_out = count(Outcome::TimedOut);
+
+            let mut runtimes = entries
+                .iter()
+                .filter(|e| e.outcome == Outcome::Succeeded)
+                .filter_map(|e| e.wall_time)
+                .collect::<Vec<f64>>();
+            runtimes.sort_by(f64::total_cmp);
+            let hours = entries.iter().filter_map(|e| e.get_elapsed()).sum::<f64>() / 3600.0;
+
+            ProjectStats {
+                project_id,
+                // Names can change, the most recent one is shown
+                project: entries.last().map(|e| e.project.clone()).unwrap_or_default(),
+                platform,
+                assignments: entries.len(),
+                succeeded,
+                failed,
+                timed_out,
+                per_hour: if hours > 0.0 { succeeded as f64 / hours } else { 0.0 },
+                mean_runtime: if runtimes.is_empty() {
+                    None
+                } else {
+                    Some(runtimes.iter().sum::<f64>() / runtimes.len() as f64)
+                },
+                p95_runtime: get_percentile(&runtimes, 0.95),
+                failure_rate: failed as f64 / entries.len() as f64,
+                timeout_rate: timed_out as f64 / entries.len() as f64,
+            }
+        })
+        .collect()
+}
+
+/// Returns the nearest-rank percentile of sorted `values`.
+fn get_percentile(values: &[f64], percentile: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let rank = (percentile * values.len() as f64).ceil() as usize;
+    Some(values[rank.clamp(1, values.len()) - 1])
+}
+
+/// Reads the history matching `filter` off the async runtime and aggregates it.
+pub async fn query(
+    history: &History,
+    filter: HistoryFilter,
+    by_platform: bool,
+) -> Result<Vec<ProjectStats>, String> {
+    let history = history.clone();
+    tokio::task::spawn_blocking(move || history.query(&filter).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|entries| aggregate(&entries, by_platform))
+}
+
+/// Keeps the scheduler's efficiency of each project up to date with the recent history.
+pub struct EfficiencyRefresher {
+    history: History,
+    scheduler: Scheduler,
+}
+
+impl EfficiencyRefresher {
+    pub fn new(history: &History, scheduler: &Scheduler) -> EfficiencyRefresher {
+        EfficiencyRefresher {
+            history: history.clone(),
+            scheduler: scheduler.clone(),
+        }
+    }
+
+    pub async fn run(&self) {
+        loop {
+            let filter = HistoryFilter {
+                since: SystemTime::now().checked_sub(EFFICIENCY_WINDOW),
+                ..HistoryFilter::default()
+            };
+            match query(&self.history, filter, false).await {
+                Ok(stats) => {
+                    let efficiency = stats
+                        .iter()
+                        .filter_map(|s| s.get_efficiency().map(|efficiency| (s.project_id, efficiency)))
+                        .collect::<HashMap<i64, f64>>();
+                    self.scheduler.set_efficiency(efficiency);
+                }
+                Err(err) => error!("<red>Unable to compute project statistics: {}</>", err),
+            }
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry of a project that took `elapsed` seconds, of which the binary ran for `wall_time`.
+    fn entry(project_id: i64, platform: Option<&str>, outcome: Outcome, elapsed: u64, wall_time: f64) -> HistoryEntry {
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let mut entry = HistoryEntry::new(1, project_id, &format!("project-{}", project_id), started, outcome);
+        entry.finished_at = humantime::format_rfc3339_millis(started + Duration::from_secs(elapsed)).to_string();
+        entry.platform = platform.map(str::to_string);
+        entry.wall_time = Some(wall_time);
+        entry
+    }
+
+    #[test]
+    fn percentile_edge_cases() {
+        assert_eq!(get_percentile(&[], 0.95), None);
+        assert_eq!(get_percentile(&[4.0], 0.95), Some(4.0));
+        assert_eq!(get_percentile(&[4.0], 0.0), Some(4.0));
+        assert_eq!(get_percentile(&[1.0, 2.0], 0.5), Some(1.0));
+        assert_eq!(get_percentile(&[1.0, 2.0], 0.95), Some(2.0));
+
+        let values = (1..=100).map(f64::from).collect::<Vec<f64>>();
+        assert_eq!(get_percentile(&values, 0.95), Some(95.0));
+        assert_eq!(get_percentile(&values, 1.0), Some(100.0));
+    }
+
+    #[test]
+    fn aggregate_empty() {
+        assert!(aggregate(&[], false).is_empty());
+        assert!(aggregate(&[], true).is_empty());
+    }
+
+    #[test]
+    fn aggregate_single_sample() {
+        let stats = aggregate(&[entry(12, Some("linux"), Outcome::Succeeded, 1800, 1500.0)], false);
+
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!((stats.project_id, stats.project.as_str(), stats.platform.as_deref()), (12, "project-12", None));
+        assert_eq!((stats.assignments, stats.succeeded, stats.failed, stats.timed_out), (1, 1, 0, 0));
+        assert_eq!(stats.per_hour, 2.0);
+        assert_eq!(stats.mean_runtime, Some(1500.0));
+        assert_eq!(stats.p95_runtime, Some(1500.0));
+        assert_eq!((stats.failure_rate, stats.timeout_rate), (0.0, 0.0));
+        assert_eq!(stats.get_efficiency(), None);
+    }
+
+    #[test]
+    fn aggregate_only_failures() {
+        let entries = [
+            entry(12, None, Outcome::Failed, 0, 0.0),
+            entry(12, None, Outcome::TimedOut, 0, 60.0),
+        ];
+        let stats = &aggregate(&entries, false)[0];
+
+        // No time was spent, and no runtime of a succeeded assignment is known
+        assert_eq!(stats.per_hour, 0.0);
+        assert_eq!((stats.mean_runtime, stats.p95_runtime), (None, None));
+        assert_eq!((stats.failure_rate, stats.timeout_rate), (0.5, 0.5));
+    }
+
+    #[test]
+    fn aggregate_by_project_and_platform() {
+        let entries = [
+            entry(13, Some("linux-generic"), Outcome::Succeeded, 600, 10.0),
+            entry(12, Some("linux-avx2"), Outcome::Succeeded, 600, 20.0),
+            entry(12, Some("linux-generic"), Outcome::Succeeded, 600, 40.0),
+            entry(12, Some("linux-avx2"), Outcome::Failed, 600, 5.0),
+            entry(12, None, Outcome::Failed, 600, 0.0),
+        ];
+
+        let stats = aggregate(&entries, false);
+        assert_eq!(stats.iter().map(|s| s.project_id).collect::<Vec<i64>>(), vec![12, 13]);
+        assert_eq!((stats[0].assignments, stats[0].succeeded, stats[0].failed), (4, 2, 2));
+        assert_eq!(stats[0].per_hour, 3.0);
+        // Failed assignments don't count towards the runtime
+        assert_eq!(stats[0].mean_runtime, Some(30.0));
+        assert_eq!(stats[0].p95_runtime, Some(40.0));
+        assert_eq!(stats[0].failure_rate, 0.5);
+
+        let stats = aggregate(&entries, true);
+        let groups = stats
+            .iter()
+            .map(|s| (s.project_id, s.platform.as_deref(), s.assignments))
+            .collect::<Vec<(i64, Option<&str>, usize)>>();
+        assert_eq!(
+            groups,
+            vec![
+                (12, None, 1),
+                (12, Some("linux-avx2"), 2),
+                (12, Some("linux-generic"), 1),
+                (13, Some("linux-generic"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn efficiency_needs_enough_assignments() {
+        let entries = (0..MIN_ASSIGNMENTS)
+            .map(|_| entry(12, None, Outcome::Failed, 60, 0.0))
+            .collect::<Vec<HistoryEntry>>();
+        assert_eq!(aggregate(&entries[1..], false)[0].get_efficiency(), None);
+        assert_eq!(aggregate(&entries, false)[0].get_efficiency(), Some(MIN_EFFICIENCY));
+    }
+}
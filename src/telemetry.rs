@@ -0,0 +1,175 @@
+//! Tracing spans around the steps of an assignment, so a slow assignment can be pinned on the
+//! feeder, a download, the binary or the submission.
+//!
+//! Span durations are logged at the debug level, and exported to an OpenTelemetry collector over
+//! OTLP/HTTP when an endpoint is configured.
+
+use std::fmt::{self, Write};
+use std::time::Instant;
+
+use opentelemetry::sdk::trace::{self, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use serde::{Deserialize, Serialize};
+use simplelog::{debug, info};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{Layer, Registry};
+
+/// Path of the traces on an OTLP/HTTP collector.
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Settings of the span exporter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingSettings {
+    /// OTLP/HTTP collector to export spans to, e.g. `http://127.0.0.1:4318`. Spans are only
+    /// logged if unset.
+    pub otlp_endpoint: Option<String>,
+    /// Service name the spans are exported under.
+    pub service_name: String,
+}
+
+impl Default for TracingSettings {
+    fn default() -> TracingSettings {
+        TracingSettings {
+            otlp_endpoint: None,
+            service_name: "dicc-client".to_string(),
+        }
+    }
+}
+
+impl TracingSettings {
+    /// Returns the URL the spans are POSTed to, adding the traces path to a bare collector URL.
+    pub fn get_traces_url(&self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let endpoint = match &self.otlp_endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+
+        let mut url = reqwest::Url::parse(endpoint)
+            .map_err(|e| format!("invalid tracing.otlp_endpoint `{}`: {}", endpoint, e))?;
+        if url.path() == "/" {
+            url.set_path(TRACES_PATH);
+        }
+        Ok(Some(url.to_string()))
+    }
+}
+
+/// The installed span subscriber, flushing the exporter on [`Telemetry::shutdown`].
+pub struct Telemetry {
+    exporting: bool,
+}
+
+/// Installs the span subscriber for the whole process.
+///
+/// Does nothing if another subscriber is already installed, such as by a service embedding the
+/// client.
+pub fn init(settings: &TracingSettings) -> Result<Telemetry, Box<dyn std::error::Error>> {
+    let tracer = match settings.get_traces_url()? {
+        Some(url) => {
+            info!("<green><bold>Exporting spans to {}</>", url);
+            Some(create_tracer(&url, &settings.service_name)?)
+        }
+        None => None,
+    };
+
+    let exporting = tracer.is_some();
+    // Only the client's own spans, not those of the HTTP stack or of the exporter itself
+    let subscriber = Registry::default()
+        .with(Targets::new().with_target(env!("CARGO_CRATE_NAME"), LevelFilter::TRACE))
+        .with(SpanTimings)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+    let _ = tracing::subscriber::set_global_default(subscriber);
+    Ok(Telemetry { exporting })
+}
+
+fn create_tracer(url: &str, service_name: &str) -> Result<Tracer, Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::new_exporter().http().with_endpoint(url);
+    let config = trace::config().with_resource(Resource::new(vec![
+        KeyValue::new("service.name", service_name.to_string()),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(opentelemetry::runtime::Tokio)
+        .map_err(|e| format!("unable to set up the OTLP exporter: {}", e))?;
+    Ok(tracer)
+}
+
+impl Telemetry {
+    /// Exports the spans still buffered.
+    pub async fn shutdown(self) {
+        if self.exporting {
+            // Shutting down blocks until the exporter is done
+            let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+        }
+    }
+}
+
+/// Logs how long each span took, with its fields.
+struct SpanTimings;
+
+struct Timing {
+    started: Instant,
+    fields: String,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanTimings {
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        let mut fields = FieldWriter(String::new());
+        attributes.record(&mut fields);
+        if let Some(span) = context.span(id) {
+            span.extensions_mut().insert(Timing {
+                started: Instant::now(),
+                fields: fields.0,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        if let Some(span) = context.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                let mut fields = FieldWriter(std::mem::take(&mut timing.fields));
+                values.record(&mut fields);
+                timing.fields = fields.0;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, context: Context<'_, S>) {
+        if let Some(span) = context.span(&id) {
+            if let Some(timing) = span.extensions().get::<Timing>() {
+                debug!(
+                    "<cyan>{} took {:.3}s</> {}",
+                    span.name(),
+                    timing.started.elapsed().as_secs_f64(),
+                    timing.fields
+                );
+            }
+        }
+    }
+}
+
+/// Writes span fields as `name=value` pairs.
+struct FieldWriter(String);
+
+impl Visit for FieldWriter {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{}={:?}", field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+
+/// Name of the lock file, holding the pid of the client using the directory.
+const LOCK_FILE: &str = "dicc-client.lock";
+
+/// The directory holding all of the client's caches, inputs, state and logs.
+///
+/// The directory is locked while this is alive, so two clients can't use it at once, unless it
+/// was opened with [`DataDir::open_shared`].
+pub struct DataDir {
+    path: PathBuf,
+    _lock: Option<File>,
+}
+
+impl DataDir {
+    /// Returns the default data directory, `$XDG_DATA_HOME/dicc-client` on Linux.
+    ///
+    /// The Docker image uses `/client/data` instead, through `DICC_DATA_DIR`.
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .map(|dir| dir.join("dicc-client"))
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    /// Creates the directory if needed and locks it.
+    pub fn open(path: &Path) -> Result<DataDir, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(path)
+            .map_err(|e| format!("unable to create data directory {}: {}", path.display(), e))?;
+
+        let lock_path = path.join(LOCK_FILE);
+        let mut lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|e| format!("unable to open lock file {}: {}", lock_path.display(), e))?;
+
+        if lock.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            let _ = lock.read_to_string(&mut pid);
+            return Err(format!(
+                "data directory {} is in use by another client (pid {}), use --data-dir to pick another one",
+                path.display(),
+                pid.trim()
+            )
+            .into());
+        }
+
+        lock.set_len(0)?;
+        lock.seek(SeekFrom::Start(0))?;
+        write!(lock, "{}", std::process::id())?;
+        lock.sync_data()?;
+
+        Ok(DataDir {
+            path: path.to_path_buf(),
+            _lock: Some(lock),
+        })
+    }
+
+    /// Creates the directory if needed without locking it, for commands that can run next to a
+    /// client using it, such as listing projects or running a binary locally.
+    ///
+    /// These only add to the caches, whose downloads are written atomically.
+    pub fn open_shared(path: &Path) -> Result<DataDir, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(path)
+            .map_err(|e| format!("unable to create data directory {}: {}", path.display(), e))?;
+        Ok(DataDir {
+            path: path.to_path_buf(),
+            _lock: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -11,4 +11,76 @@ pub async fn set_executable(path: &Path) {
         let perms = Permissions::from_mode(0o755);
         fs::set_permissions(path, perms).await.expect("failed to set permissions");
     }
-}
\ No newline at end of file
+}
+
+/// Checks that a name from the server can be used as a single path component, so it can't
+/// escape the directory it's joined to.
+pub fn is_safe_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.len() <= 255
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+}
+
+/// Returns the total size of the files under `path`, or 0 if it doesn't exist.
+pub fn get_size(path: &Path) -> u64 {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    std::fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| get_size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+/// Formats a byte count for humans, e.g. `12.3 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_file_names() {
+        assert!(is_safe_file_name("bin.sh"));
+        assert!(is_safe_file_name("seeds-1.2_linux+avx2"));
+        assert!(is_safe_file_name(".hidden"));
+        assert!(is_safe_file_name("..."));
+    }
+
+    #[test]
+    fn unsafe_file_names() {
+        assert!(!is_safe_file_name(""));
+        assert!(!is_safe_file_name("."));
+        assert!(!is_safe_file_name(".."));
+        assert!(!is_safe_file_name("../bin.sh"));
+        assert!(!is_safe_file_name("dir/bin.sh"));
+        assert!(!is_safe_file_name("/etc/passwd"));
+        assert!(!is_safe_file_name("dir\\bin.exe"));
+        assert!(!is_safe_file_name("%2e%2e"));
+        assert!(!is_safe_file_name("..%2fbin.sh"));
+        assert!(!is_safe_file_name("bin.sh\0"));
+        assert!(!is_safe_file_name("bin sh"));
+        assert!(!is_safe_file_name(&"a".repeat(256)));
+    }
+}
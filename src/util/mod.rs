@@ -1 +1,4 @@
-pub mod file;
\ No newline at end of file
+pub mod data_dir;
+pub mod file;
+pub mod process;
+pub mod secret;
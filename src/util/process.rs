@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::manager::pool::ResourceLimits;
+
+/// Applies the memory and niceness limits to the command's process once it's spawned.
+#[allow(unused_variables)]
+pub fn apply_limits(command: &mut Command, limits: &ResourceLimits) {
+    #[cfg(not(target_os = "windows"))]
+    {
+        let memory = limits.memory;
+        let nice = limits.nice;
+        if memory.is_none() && nice.is_none() {
+            return;
+        }
+
+        // SAFETY: only async-signal-safe libc calls are made between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some(memory) = memory {
+                    let limit = libc::rlimit {
+                        rlim_cur: memory as libc::rlim_t,
+                        rlim_max: memory as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Waits for a child process to exit without reaping it, then returns the CPU time used by it
+/// and the children it waited for.
+///
+/// The process must still be waited on as usual afterwards. Returns `None` where the OS doesn't
+/// report the CPU time of exited processes.
+#[cfg(target_os = "linux")]
+pub fn wait_cpu_time(pid: u32) -> Option<Duration> {
+    loop {
+        // SAFETY: waitid only writes to the siginfo it's given.
+        let result = unsafe {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT)
+        };
+        if result == 0 {
+            break;
+        }
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+
+    // The zombie keeps its user and system times, and those of its waited for children
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = stat.rsplit_once(')')?.1.split_whitespace().collect::<Vec<&str>>();
+    let ticks = fields
+        .get(11..15)?
+        .iter()
+        .map(|field| field.parse::<u64>().ok())
+        .sum::<Option<u64>>()?;
+
+    // SAFETY: sysconf has no preconditions.
+    let per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if per_second <= 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(ticks as f64 / per_second as f64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn wait_cpu_time(_pid: u32) -> Option<Duration> {
+    None
+}
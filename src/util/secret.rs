@@ -0,0 +1,81 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+/// Placeholder shown instead of secrets.
+pub const REDACTED: &str = "<redacted>";
+
+/// A string that is never printed or serialized, such as an API key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Secret {
+        Secret(value.to_string())
+    }
+
+    /// Returns the actual value, only to be used where it's sent to the API.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether `value` is the secret, in a time that doesn't depend on where they differ.
+    ///
+    /// Both are hashed first so the comparison doesn't reveal the length of the secret either.
+    pub fn matches(&self, value: &[u8]) -> bool {
+        let expected = Sha256::digest(self.0.as_bytes());
+        let actual = Sha256::digest(value);
+        expected.iter().zip(actual.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Secret, D::Error> {
+        Ok(Secret(String::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{}", secret), REDACTED);
+        assert_eq!(format!("{:?}", secret), REDACTED);
+        assert_eq!(format!("{:?}", Some(secret.clone())), format!("Some({})", REDACTED));
+        assert_eq!(serde_json::to_string(&secret).unwrap(), format!("\"{}\"", REDACTED));
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn matches_only_the_exact_value() {
+        let secret = Secret::new("hunter2");
+        assert!(secret.matches(b"hunter2"));
+        assert!(!secret.matches(b"hunter"));
+        assert!(!secret.matches(b"hunter22"));
+        assert!(!secret.matches(b"Hunter2"));
+        assert!(!secret.matches(b""));
+        assert!(Secret::new("").matches(b""));
+    }
+}